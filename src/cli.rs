@@ -1,16 +1,25 @@
-use clap::{Parser, Subcommand};
+use clap::builder::{PossibleValuesParser, TypedValueParser};
+use clap::{Command, Parser, Subcommand};
+use clap_complete::ArgValueCompleter;
 use std::path::PathBuf;
 use std::str::FromStr;
+use todotxt::query::{Cmp, DateField};
+use todotxt::{deps, i18n, t, Codec, Date, Priority, Query, Recurrence, Task};
 
-use crate::date::Date;
-use crate::priority::Priority;
+use crate::completion;
+use crate::config;
+use crate::output::Format;
 
 bitflags::bitflags! {
-    #[derive(Clone)]
+    #[derive(Clone, Copy, PartialEq)]
     pub struct DisplayMode: u8 {
-        const PENDING = 0b0001;
-        const COMPLETED = 0b0010;
-        const REMOVED = 0b0100;
+        const PENDING = 0b00001;
+        const COMPLETED = 0b00010;
+        const REMOVED = 0b00100;
+        const IN_PROGRESS = 0b01000;
+        const WAITING = 0b10000;
+        /// 未完成的任务，即未开始、进行中和等待中
+        const OPEN = Self::PENDING.bits() | Self::IN_PROGRESS.bits() | Self::WAITING.bits();
     }
 }
 
@@ -22,9 +31,11 @@ impl FromStr for DisplayMode {
         for c in s.chars() {
             match c {
                 'p' => mode |= DisplayMode::PENDING,
+                'i' => mode |= DisplayMode::IN_PROGRESS,
+                'w' => mode |= DisplayMode::WAITING,
                 'c' => mode |= DisplayMode::COMPLETED,
                 'r' => mode |= DisplayMode::REMOVED,
-                _ => return Err(format!("无效的模式字符: {}", c)),
+                _ => return Err(t!("error.invalid_mode", c)),
             }
         }
         Ok(mode)
@@ -33,11 +44,17 @@ impl FromStr for DisplayMode {
 
 #[derive(Parser)]
 pub struct TaskConf {
-    /// 关键词
+    /// 关键词或查询语句，如 'tag:rust and (pri>=B or due<+3d) and not @home'
     pub keyword: Option<String>,
     /// 标签
-    #[arg(short, long)]
+    #[arg(short, long, add = ArgValueCompleter::new(completion::tags))]
     pub tag: Option<String>,
+    /// 项目 (+project)
+    #[arg(long, add = ArgValueCompleter::new(completion::projects))]
+    pub project: Option<String>,
+    /// 上下文 (@context)
+    #[arg(long, add = ArgValueCompleter::new(completion::contexts))]
+    pub context: Option<String>,
     /// 优先级
     #[arg(short, long)]
     pub priority: Option<Priority>,
@@ -47,6 +64,94 @@ pub struct TaskConf {
     /// 按 优先级/截止日期 排序
     #[arg(short, long)]
     pub sort_by: Option<String>,
+    /// 只显示前置任务尚未完成的任务
+    #[arg(long, conflicts_with = "actionable")]
+    pub blocked: bool,
+    /// 隐藏前置任务尚未完成的任务
+    #[arg(long)]
+    pub actionable: bool,
+}
+
+impl TaskConf {
+    /// 将关键词（作为查询语句）与各个筛选参数组合为一个查询，
+    /// 任务是否阻塞取决于清单中的其他任务
+    pub fn query(&self, tasks: &[Task]) -> todotxt::Result<Query> {
+        let mut query = match &self.keyword {
+            Some(keyword) => Query::parse(keyword)?,
+            None => Query::All,
+        };
+        if let Some(tag) = &self.tag {
+            query = query.and(Query::Tag(tag.clone()));
+        }
+        if let Some(project) = &self.project {
+            query = query.and(Query::Project(project.clone()));
+        }
+        if let Some(context) = &self.context {
+            query = query.and(Query::Context(context.clone()));
+        }
+        if let Some(priority) = self.priority {
+            query = query.and(Query::Priority(Cmp::Ge(priority)));
+        }
+        if let Some(due_to) = self.due_to {
+            query = query.and(Query::Date(DateField::Due, Cmp::Le(due_to)));
+        }
+        if self.blocked || self.actionable {
+            let blocked = Query::Ids(deps::blocked(tasks));
+            query = query.and(if self.blocked {
+                blocked
+            } else {
+                Query::Not(Box::new(blocked))
+            });
+        }
+        Ok(query)
+    }
+}
+
+#[derive(Parser)]
+pub struct Target {
+    #[command(flatten)]
+    pub conf: TaskConf,
+    /// 任务编号，指定后不再交互式选择 (示例: 1 3 4)
+    #[arg(value_delimiter = ',')]
+    pub ids: Vec<usize>,
+    /// 任务编号，以逗号分隔 (示例: 2,4)
+    #[arg(long = "ids", value_name = "IDS", value_delimiter = ',')]
+    pub extra_ids: Vec<usize>,
+}
+
+impl Target {
+    pub fn has_ids(&self) -> bool {
+        !self.ids.is_empty()
+            || !self.extra_ids.is_empty()
+            || self.conf.keyword.as_deref().is_some_and(is_id_list)
+    }
+
+    /// 汇总指定的任务编号，关键词为纯数字时也视为任务编号
+    pub fn take_ids(&mut self) -> Vec<usize> {
+        let mut ids = Vec::new();
+        if let Some(keyword) = self.conf.keyword.take_if(|k| is_id_list(k)) {
+            ids.extend(keyword.split(',').filter_map(|s| s.parse::<usize>().ok()));
+        }
+        ids.append(&mut self.ids);
+        ids.append(&mut self.extra_ids);
+        ids
+    }
+}
+
+fn is_id_list(s: &str) -> bool {
+    s.split(',').all(|id| id.parse::<usize>().is_ok())
+}
+
+/// 只接受配置中已启用的优先级
+fn parse_priority(s: &str) -> todotxt::Result<Priority> {
+    config::get().check_priority(s.parse()?)
+}
+
+/// 将 `30d` 这样的时长转换为相应天数之前的日期
+fn parse_age(s: &str) -> todotxt::Result<Date> {
+    format!("-{}", s.trim_start_matches('+'))
+        .parse()
+        .map_err(|_| todotxt::Error::InvalidDate(s.to_string()))
 }
 
 #[derive(Subcommand)]
@@ -56,40 +161,157 @@ pub enum Action {
         /// 任务内容
         content: String,
         /// 优先级
-        #[arg(short, long)]
+        #[arg(short, long, value_parser = parse_priority)]
         priority: Option<Priority>,
         /// 截止日期
         #[arg(short, long)]
         due_to: Option<Date>,
+        /// 重复规则，如 1w 表示完成一周后再次到期，+1m 表示按原截止日期每月到期
+        #[arg(short, long)]
+        rec: Option<Recurrence>,
+        /// 父任务的编号，新任务作为它的子任务
+        #[arg(long, value_name = "ID")]
+        parent: Option<usize>,
+        /// 前置任务的编号，以逗号分隔，它们完成之前新任务处于阻塞状态
+        #[arg(long, value_name = "IDS", value_delimiter = ',')]
+        dep: Vec<usize>,
     },
     /// 列出任务
     List {
-        /// 显示模式
-        #[arg(short, long, default_value = "p")]
-        mode: DisplayMode,
+        /// 显示模式，默认为 piw
+        #[arg(short, long)]
+        mode: Option<DisplayMode>,
+        /// 输出格式
+        #[arg(long, value_enum, default_value_t = Format::Human)]
+        format: Format,
         #[command(flatten)]
         conf: TaskConf,
     },
+    /// 列出所有项目及其任务数
+    Projects {
+        /// 显示模式，默认为 piw
+        #[arg(short, long)]
+        mode: Option<DisplayMode>,
+    },
+    /// 列出所有上下文及其任务数
+    Contexts {
+        /// 显示模式，默认为 piw
+        #[arg(short, long)]
+        mode: Option<DisplayMode>,
+    },
     /// 完成任务
     Done {
         #[command(flatten)]
-        conf: TaskConf,
+        target: Target,
+        /// 前置任务尚未完成时也完成任务
+        #[arg(long)]
+        force: bool,
     },
     /// 修改任务
     Modify {
         #[command(flatten)]
-        conf: TaskConf,
+        target: Target,
+        /// 新的任务内容 (仅在指定任务编号时生效)
+        #[arg(short, long)]
+        content: Option<String>,
+    },
+    /// 开始任务，标记为进行中
+    Start {
+        #[command(flatten)]
+        target: Target,
+    },
+    /// 将任务标记为等待中
+    Wait {
+        #[command(flatten)]
+        target: Target,
     },
     /// 移除任务
     Remove {
         #[command(flatten)]
-        conf: TaskConf,
+        target: Target,
     },
     /// 删除任务
     Delete {
+        #[command(flatten)]
+        target: Target,
+    },
+    /// 撤销最近的操作
+    Undo {
+        /// 要撤销的操作数
+        #[arg(default_value_t = 1)]
+        count: usize,
+    },
+    /// 重做被撤销的操作
+    Redo {
+        /// 要重做的操作数
+        #[arg(default_value_t = 1)]
+        count: usize,
+    },
+    /// 检查任务清单中无法解析的行
+    Check {
+        /// 尝试修复常见的格式错误
+        #[arg(long)]
+        fix: bool,
+    },
+    /// 将已完成和已移除的任务移入归档文件
+    Archive {
+        /// 要归档的任务状态，只能包含 c 和 r
+        #[arg(long, default_value = "cr")]
+        state: DisplayMode,
+        /// 只归档完成（移除的任务按创建）于此之前的任务，如 30d、2w、6m
+        #[arg(long, value_name = "AGE", value_parser = parse_age)]
+        older_than: Option<Date>,
+    },
+    /// 在编辑器中编辑任务
+    Edit {
+        /// 显示模式，默认为 piw
+        #[arg(short, long)]
+        mode: Option<DisplayMode>,
+        #[command(flatten)]
+        conf: TaskConf,
+    },
+    /// 全屏交互界面
+    Tui {
+        /// 初始的显示模式，默认为 piw
+        #[arg(short, long)]
+        mode: Option<DisplayMode>,
+        #[command(flatten)]
+        conf: TaskConf,
+    },
+    /// 查看操作历史
+    History {
+        /// 显示的条目数
+        #[arg(short = 'n', long, default_value_t = 10)]
+        limit: usize,
+    },
+    /// 导出任务
+    Export {
+        /// 导出为 iCalendar (VTODO) 格式
+        #[arg(long, required = true)]
+        ical: bool,
+        /// 写入的文件，默认为标准输出
+        #[arg(short, long, value_name = "FILE")]
+        output: Option<PathBuf>,
+        /// 显示模式，默认为 piw
+        #[arg(short, long)]
+        mode: Option<DisplayMode>,
         #[command(flatten)]
         conf: TaskConf,
     },
+    /// 导入任务，UID 相同的任务会被更新而不是重复添加
+    Import {
+        /// 要导入的 iCalendar 文件
+        #[arg(long, value_name = "FILE")]
+        ical: PathBuf,
+    },
+    /// 输出 shell 的补全脚本
+    Completions {
+        /// shell 的名称
+        #[arg(value_parser = PossibleValuesParser::new(completion::shells()))]
+        shell: String,
+    },
+    /// 输出 man 手册
+    Man,
 }
 
 #[derive(Parser)]
@@ -98,7 +320,59 @@ pub enum Action {
 pub struct Args {
     #[command(subcommand)]
     pub action: Action,
-    /// 指定任务清单文件
-    #[arg(short, long, default_value = "todo.txt")]
+    /// 指定任务清单文件，默认为当前目录或主目录下的 todo.txt
+    #[arg(short, long)]
     pub file: Option<PathBuf>,
+    /// 指定归档文件，默认为任务清单所在目录下的 done.txt
+    #[arg(long, global = true, value_name = "FILE")]
+    pub archive: Option<PathBuf>,
+    /// 指定任务清单的格式，未指定时根据文件内容推断
+    #[arg(
+        long,
+        global = true,
+        value_parser = PossibleValuesParser::new(["native", "todotxt"])
+            .map(|s| s.parse::<Codec>().unwrap()),
+    )]
+    pub codec: Option<Codec>,
+    /// 遇到无法解析的行时报错，而不是原样保留
+    #[arg(long, global = true)]
+    pub strict: bool,
+}
+
+/// 按当前语言替换帮助信息：子命令的说明为 `help.<子命令>.about`，参数的说明为
+/// `help.<子命令>.<参数>`，其次为各子命令共用的 `help.arg.<参数>`，都没有时保留文档注释
+pub fn localize(command: Command) -> Command {
+    let command = localize_args(command.about(t!("help.about")), None);
+    let names: Vec<String> = command
+        .get_subcommands()
+        .map(|sub| sub.get_name().to_string())
+        .collect();
+    names.iter().fold(command, |command, name| {
+        command.mut_subcommand(name, |sub| {
+            let sub = match help(Some(name), "about") {
+                Some(about) => sub.about(about),
+                None => sub,
+            };
+            localize_args(sub, Some(name))
+        })
+    })
+}
+
+fn localize_args(command: Command, scope: Option<&str>) -> Command {
+    let ids: Vec<String> = command
+        .get_arguments()
+        .map(|arg| arg.get_id().to_string())
+        .collect();
+    ids.iter()
+        .fold(command, |command, id| match help(scope, id) {
+            Some(text) => command.mut_arg(id, |arg| arg.help(text)),
+            None => command,
+        })
+}
+
+fn help(scope: Option<&str>, key: &str) -> Option<&'static str> {
+    let catalog = i18n::catalog();
+    scope
+        .and_then(|scope| catalog.get(&format!("help.{}.{}", scope, key)))
+        .or_else(|| catalog.get(&format!("help.arg.{}", key)))
 }
@@ -0,0 +1,390 @@
+use regex::Regex;
+use std::fmt;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+use std::str::FromStr;
+
+use crate::error::{Error, Result};
+use crate::parser::{self, LineError, LineResult};
+use crate::priority::Priority;
+use crate::state::State;
+use crate::t;
+use crate::task::{join_ids, Task};
+
+/// 任务清单的文本格式
+#[derive(Clone, Copy, PartialEq)]
+pub enum Codec {
+    /// 本程序的格式: ✓ [A] 内容 (创建日期) (due:截止日期) (完成日期)
+    Native,
+    /// 标准 todo.txt 格式: x 完成日期 创建日期 (A) 内容 due:截止日期
+    Todotxt,
+}
+
+impl Codec {
+    /// 根据文件开头若干个非空行的形式推断格式，文件不存在或为空时使用本程序的格式
+    pub fn detect(file_path: &Path) -> Self {
+        let Ok(file) = File::open(file_path) else {
+            return Self::Native;
+        };
+        let re_native = Regex::new(r"^(?:[▶⏸✓✗] ?)?\[.?\]").unwrap();
+        let lines: Vec<String> = BufReader::new(file)
+            .lines()
+            .map_while(|line| line.ok())
+            .filter(|line| !line.trim().is_empty())
+            .take(20)
+            .collect();
+        if lines.is_empty() || lines.iter().any(|line| re_native.is_match(line.trim())) {
+            Self::Native
+        } else {
+            Self::Todotxt
+        }
+    }
+
+    pub fn stringify(&self, task: &Task) -> String {
+        match self {
+            Self::Native => task.stringify(),
+            Self::Todotxt => stringify_todotxt(task),
+        }
+    }
+}
+
+impl fmt::Display for Codec {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Native => f.write_str("native"),
+            Self::Todotxt => f.write_str("todotxt"),
+        }
+    }
+}
+
+impl FromStr for Codec {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "native" => Ok(Self::Native),
+            "todotxt" => Ok(Self::Todotxt),
+            _ => Err(Error::InvalidInput(t!("error.invalid_codec", s))),
+        }
+    }
+}
+
+/// 标准 todo.txt 格式的单行解析器
+pub struct TodoTxtParser {
+    re_date: Regex,
+    re_priority: Regex,
+    re_tag: Regex,
+    re_project: Regex,
+    re_context: Regex,
+}
+
+impl TodoTxtParser {
+    pub fn new() -> Self {
+        Self {
+            re_date: Regex::new(r"^\d{4}-\d{2}-\d{2}$").unwrap(),
+            re_priority: Regex::new(r"^\(([A-Z])\)$").unwrap(),
+            re_tag: parser::tag_regex(),
+            re_project: parser::project_regex(),
+            re_context: parser::context_regex(),
+        }
+    }
+
+    pub fn parse_line(&self, line: &str) -> LineResult<Task> {
+        // 每个词及其在行中的字节偏移量，用于报告出错的位置
+        let mut words = line
+            .split_whitespace()
+            .map(|word| (word.as_ptr() as usize - line.as_ptr() as usize, word))
+            .peekable();
+        let is_date =
+            |word: Option<&(usize, &str)>| word.is_some_and(|(_, w)| self.re_date.is_match(w));
+        let at = |offset: usize| move |error: Error| LineError::at(line, offset, error);
+
+        let mut state = State::Pending;
+        let mut completed_at = None;
+        let mut priority = Priority::default();
+        if words.peek().is_some_and(|(_, w)| *w == "x") {
+            words.next();
+            state = State::Completed;
+            if is_date(words.peek()) {
+                let (offset, word) = words.next().unwrap();
+                completed_at = Some(parser::parse_date(word).map_err(at(offset))?);
+            }
+        }
+        if let Some((offset, caps)) = words
+            .peek()
+            .and_then(|(offset, w)| Some((*offset, self.re_priority.captures(w)?)))
+        {
+            priority = caps[1].parse().map_err(at(offset + 1))?;
+            words.next();
+        }
+        // 已完成的任务只有一个日期时，它是完成日期，任务没有创建日期
+        let created_at = if is_date(words.peek()) {
+            let (offset, word) = words.next().unwrap();
+            Some(parser::parse_date(word).map_err(at(offset))?)
+        } else {
+            None
+        };
+        // 已完成的任务的优先级也可以写在日期之后，如 `x 2024-06-29 2024-06-27 (A) text`
+        if state == State::Completed && priority == Priority::NONE {
+            if let Some((offset, caps)) = words
+                .peek()
+                .and_then(|(offset, w)| Some((*offset, self.re_priority.captures(w)?)))
+            {
+                priority = caps[1].parse().map_err(at(offset + 1))?;
+                words.next();
+            }
+        }
+
+        let mut task = Task::new(priority, String::new(), None);
+        task.state = state;
+        task.created_at = created_at;
+        task.completed_at = completed_at;
+
+        // 内容从第一个词开始截取原文，保留其中的空白；取出字段的位置以一个空格连接
+        let mut content = String::new();
+        let mut last_end = None;
+        let mut field_between = false;
+        for (offset, word) in words {
+            if parse_field(&mut task, word) {
+                field_between = true;
+                continue;
+            }
+            match last_end {
+                Some(_) if field_between => content.push(' '),
+                Some(end) => content.push_str(&line[end..offset]),
+                None => (),
+            }
+            content.push_str(word);
+            last_end = Some(offset + word.len());
+            field_between = false;
+        }
+        if content.is_empty() {
+            return Err(LineError::at(
+                line,
+                0,
+                Error::InvalidLine(t!("error.missing_content").to_string()),
+            ));
+        }
+        task.content = content;
+        task.tags = parser::parse_tags(&task.content, &self.re_tag);
+        task.projects = parser::parse_tags(&task.content, &self.re_project);
+        task.contexts = parser::parse_tags(&task.content, &self.re_context);
+
+        Ok(task)
+    }
+}
+
+impl Default for TodoTxtParser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// 识别 `key:value` 形式的字段并写入任务，无法识别或取值无效的词留在内容中
+fn parse_field(task: &mut Task, word: &str) -> bool {
+    let Some((key, value)) = word.split_once(':') else {
+        return false;
+    };
+    match key {
+        "due" => parser::parse_date(value)
+            .map(|date| task.due_to = Some(date))
+            .is_ok(),
+        "rec" => value
+            .parse()
+            .map(|recurrence| task.recurrence = Some(recurrence))
+            .is_ok(),
+        "parent" => parser::parse_id(value)
+            .map(|id| task.parent = Some(id))
+            .is_ok(),
+        "dep" => parser::parse_ids(value)
+            .map(|ids| task.deps.extend(ids))
+            .is_ok(),
+        "uid" if !value.is_empty() => {
+            task.uid = Some(value.to_string());
+            true
+        }
+        "id" => parser::parse_id(value).map(|id| task.id = id).is_ok(),
+        "pri" => value
+            .parse()
+            .map(|priority| task.priority = priority)
+            .is_ok(),
+        "h" if value == "1" => {
+            task.state = State::Removed;
+            true
+        }
+        "state" if value == "in_progress" => {
+            task.state = State::InProgress;
+            true
+        }
+        "state" if value == "waiting" => {
+            task.state = State::Waiting;
+            true
+        }
+        _ => false,
+    }
+}
+
+/// 已移除的任务写为 `h:1`（隐藏），进行中和等待中的任务写为 `state:in_progress` 和
+/// `state:waiting`，已完成任务的优先级写在日期之后，没有的日期省略
+fn stringify_todotxt(task: &Task) -> String {
+    let mut s = String::new();
+    match task.state {
+        State::Completed => match task.completed_at {
+            Some(completed_at) => s.push_str(&format!("x {} ", completed_at.fmt())),
+            None => s.push_str("x "),
+        },
+        _ if task.priority != Priority::NONE => s.push_str(&format!("({}) ", task.priority)),
+        _ => (),
+    }
+    if let Some(created_at) = task.created_at {
+        s.push_str(&format!("{} ", created_at.fmt()));
+    }
+    if task.state == State::Completed && task.priority != Priority::NONE {
+        s.push_str(&format!("({}) ", task.priority));
+    }
+    s.push_str(&task.content);
+    if let Some(due_to) = task.due_to {
+        s.push_str(&format!(" due:{}", due_to.fmt()));
+    }
+    if let Some(recurrence) = task.recurrence {
+        s.push_str(&format!(" rec:{}", recurrence));
+    }
+    if let Some(parent) = task.parent {
+        s.push_str(&format!(" parent:{}", parent));
+    }
+    if !task.deps.is_empty() {
+        s.push_str(&format!(" dep:{}", join_ids(&task.deps)));
+    }
+    if let Some(uid) = &task.uid {
+        s.push_str(&format!(" uid:{}", uid));
+    }
+    if task.id != 0 {
+        s.push_str(&format!(" id:{}", task.id));
+    }
+    match task.state {
+        State::InProgress | State::Waiting => s.push_str(&format!(" state:{}", task.state.name())),
+        State::Removed => s.push_str(" h:1"),
+        _ => (),
+    }
+    s
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::LineParser;
+
+    fn parse(line: &str) -> Task {
+        TodoTxtParser::new().parse_line(line).unwrap()
+    }
+
+    #[test]
+    fn round_trip_keeps_lines_unchanged() {
+        for line in [
+            "(D) delta task",
+            "x 2024-01-02 plain done",
+            "x plain done without dates",
+            "x 2024-01-03 2024-01-01 done with both dates",
+            "x 2024-06-29 2024-06-27 (A) text +project @context due:2024-06-30",
+            "x (B) done without dates",
+            "(C) keeps  double spaces\tand tabs",
+            "(O) 2024-01-01 oscar",
+            "(Z) zulu",
+            "(A) 2024-01-01 call mom +family @phone due:2024-02-01 rec:+1m",
+            "2024-01-01 started state:in_progress",
+            "waiting state:waiting",
+            "hidden h:1",
+            "child parent:3 dep:1,2 id:4",
+            "from calendar uid:abc@example.com",
+            "keeps t:2024-01-01 and other key:value pairs",
+            "keeps due:tomorrow and id:abc as content",
+        ] {
+            assert_eq!(stringify_todotxt(&parse(line)), line);
+        }
+    }
+
+    #[test]
+    fn missing_creation_date_is_not_invented() {
+        let task = parse("(D) delta task");
+        assert_eq!(task.priority.as_str(), "D");
+        assert!(task.created_at.is_none());
+
+        // 已完成的任务只有一个日期时，它是完成日期
+        let task = parse("x 2024-01-02 plain done");
+        assert!(task.state == State::Completed);
+        assert_eq!(
+            task.completed_at.map(|date| date.fmt()).as_deref(),
+            Some("2024-01-02")
+        );
+        assert!(task.created_at.is_none());
+    }
+
+    #[test]
+    fn every_letter_is_a_priority() {
+        assert_eq!(parse("(O) oscar").priority.as_str(), "O");
+        assert_eq!(parse("oscar").priority.as_str(), "-");
+        // 小写字母和不在开头的括号不是优先级
+        assert_eq!(parse("(a) lower").priority.as_str(), "-");
+        assert_eq!(parse("2024-01-01 (A) later").content, "(A) later");
+    }
+
+    #[test]
+    fn native_format_keeps_missing_creation_date() {
+        let task = parse("(D) delta task");
+        let line = task.stringify();
+        assert_eq!(line, "[D] delta task (-)");
+        let task = LineParser::new().parse_line(&line).unwrap();
+        assert!(task.created_at.is_none());
+        assert_eq!(stringify_todotxt(&task), "(D) delta task");
+    }
+
+    #[test]
+    fn native_o_means_no_priority() {
+        let native = LineParser::new();
+        let legacy = native.parse_line("[O] legacy (2024-01-01)").unwrap();
+        assert_eq!(legacy.priority.as_str(), "-");
+        assert_eq!(legacy.stringify(), "[-] legacy (2024-01-01)");
+        assert_eq!(stringify_todotxt(&legacy), "2024-01-01 legacy");
+
+        let oscar = parse("(O) oscar");
+        assert_eq!(oscar.stringify(), "[o] oscar (-)");
+        let oscar = native.parse_line(&oscar.stringify()).unwrap();
+        assert_eq!(stringify_todotxt(&oscar), "(O) oscar");
+    }
+
+    #[test]
+    fn completed_priority_after_dates() {
+        let task = parse("x 2024-01-03 2024-01-01 (A) text");
+        assert_eq!(task.priority.as_str(), "A");
+        assert_eq!(task.content, "text");
+        // 也接受 `pri:A`，写回时放在日期之后
+        let task = parse("x 2024-01-03 2024-01-01 text pri:A");
+        assert_eq!(stringify_todotxt(&task), "x 2024-01-03 2024-01-01 (A) text");
+    }
+
+    #[test]
+    fn completion_date_is_not_invented() {
+        let mut task = parse("2024-01-01 text");
+        task.state = State::Completed;
+        assert_eq!(stringify_todotxt(&task), "x 2024-01-01 text");
+        task.created_at = None;
+        assert_eq!(stringify_todotxt(&task), "x text");
+    }
+
+    #[test]
+    fn unparseable_fields_stay_in_content() {
+        let task = parse("task due:tomorrow id:0 rec:often");
+        assert_eq!(task.content, "task due:tomorrow id:0 rec:often");
+        assert!(task.due_to.is_none() && task.recurrence.is_none());
+        // 取出的字段之间的内容以一个空格连接
+        assert_eq!(parse("a  due:2024-01-01  b").content, "a b");
+    }
+
+    #[test]
+    fn invalid_lines() {
+        for line in ["2024-01-01", "(A)", "x 2024-01-02"] {
+            assert!(TodoTxtParser::new().parse_line(line).is_err(), "{}", line);
+        }
+    }
+}
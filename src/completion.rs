@@ -0,0 +1,104 @@
+use clap::CommandFactory;
+use clap_complete::env::Shells;
+use clap_complete::{CompleteEnv, CompletionCandidate};
+use regex::Regex;
+use std::collections::BTreeMap;
+use std::env;
+use std::ffi::OsStr;
+use std::io;
+use std::path::PathBuf;
+use todotxt::{parser, t, Error, Result, TodoList};
+
+use crate::cli::{self, Args};
+use crate::config;
+
+/// 补全脚本调用 tdt 时设置的环境变量，值为 shell 的名称
+const COMPLETE_VAR: &str = "TDT_COMPLETE";
+
+/// 补全脚本中调用的程序，从 `PATH` 中查找
+const BIN: &str = "tdt";
+
+/// 由补全脚本调用时输出补全候选项并退出，否则直接返回
+pub fn complete() {
+    CompleteEnv::with_factory(|| cli::localize(Args::command()))
+        .var(COMPLETE_VAR)
+        .bin(BIN)
+        .completer(BIN)
+        .complete();
+}
+
+/// 输出 shell 的补全脚本，脚本在补全时调用 tdt 获取候选项，因此能补全任务清单中的标签等
+pub fn print_completions(shell: &str) -> Result<()> {
+    let shells = Shells::builtins();
+    let completer = shells
+        .completer(shell)
+        .ok_or_else(|| Error::InvalidInput(shell.to_string()))?;
+    let name = Args::command().get_name().to_string();
+    completer.write_registration(COMPLETE_VAR, &name, BIN, BIN, &mut io::stdout())?;
+    Ok(())
+}
+
+/// 输出 roff 格式的 man 手册
+pub fn print_man() -> Result<()> {
+    let command = cli::localize(Args::command()).name(BIN);
+    clap_mangen::Man::new(command).render(&mut io::stdout())?;
+    Ok(())
+}
+
+pub fn shells() -> impl Iterator<Item = &'static str> {
+    Shells::builtins().0.iter().map(|shell| shell.name())
+}
+
+pub fn tags(current: &OsStr) -> Vec<CompletionCandidate> {
+    candidates(current, &parser::tag_regex())
+}
+
+pub fn projects(current: &OsStr) -> Vec<CompletionCandidate> {
+    candidates(current, &parser::project_regex())
+}
+
+pub fn contexts(current: &OsStr) -> Vec<CompletionCandidate> {
+    candidates(current, &parser::context_regex())
+}
+
+/// 任务清单中以当前输入开头的值，附带出现该值的任务数
+fn candidates(current: &OsStr, re: &Regex) -> Vec<CompletionCandidate> {
+    let Some(current) = current.to_str() else {
+        return Vec::new();
+    };
+    let Some(list) = todo_file().and_then(|path| TodoList::open(path).ok()) else {
+        return Vec::new();
+    };
+
+    let mut counts: BTreeMap<String, usize> = BTreeMap::new();
+    for task in list.tasks() {
+        let mut values = parser::parse_tags(&task.content, re);
+        values.sort();
+        values.dedup();
+        for value in values {
+            *counts.entry(value).or_default() += 1;
+        }
+    }
+    counts
+        .into_iter()
+        .filter(|(value, _)| value.starts_with(current))
+        .map(|(value, count)| {
+            CompletionCandidate::new(value).help(Some(t!("completion.tasks", count).into()))
+        })
+        .collect()
+}
+
+/// 正在补全的命令行中 `-f` 指定的任务清单，其次为配置中的文件和默认文件
+fn todo_file() -> Option<PathBuf> {
+    let args: Vec<String> = env::args().skip_while(|arg| arg != "--").collect();
+    let mut file = None;
+    for (i, arg) in args.iter().enumerate() {
+        if let Some(path) = arg.strip_prefix("--file=") {
+            file = Some(PathBuf::from(path));
+        } else if arg == "-f" || arg == "--file" {
+            file = args.get(i + 1).map(PathBuf::from);
+        }
+    }
+    file.or_else(|| config::get().file.clone())
+        .or_else(crate::get_default_file)
+}
@@ -0,0 +1,560 @@
+use colored::Color;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::env;
+use std::ffi::OsString;
+use std::fmt;
+use std::fs;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::OnceLock;
+use todotxt::i18n::{self, Catalog};
+use todotxt::priority::MAX_LEVELS;
+use todotxt::{date, t, Date, Error, Priority, Result, State};
+
+use crate::cli::DisplayMode;
+
+static CONFIG: OnceLock<Config> = OnceLock::new();
+
+/// 主题中可以设置的颜色
+const COLOR_KEYS: [&str; 10] = [
+    "state",
+    "in_progress",
+    "waiting",
+    "priority",
+    "created",
+    "due",
+    "overdue",
+    "done",
+    "late",
+    "recurrence",
+];
+
+/// 带参数的全局选项，查找子命令时跳过它们的参数
+const VALUE_OPTIONS: [&str; 4] = ["-f", "--file", "--archive", "--codec"];
+
+/// 配置文件的内容，各项均可省略
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct RawConfig {
+    locale: Option<String>,
+    file: Option<String>,
+    archive: Option<String>,
+    date_format: Option<String>,
+    backup: Option<bool>,
+    add: RawAdd,
+    list: RawList,
+    priority: RawPriority,
+    colors: HashMap<String, String>,
+    alias: HashMap<String, String>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct RawAdd {
+    priority: Option<String>,
+    due: Option<String>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct RawPriority {
+    levels: Option<usize>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct RawList {
+    mode: Option<String>,
+    sort: Option<String>,
+}
+
+impl RawConfig {
+    /// 以 `TDT_*` 变量覆盖配置文件中的各项，`var` 返回变量的值，未设置或为空时返回 None
+    fn override_with(&mut self, var: impl Fn(&str) -> Option<String>) -> Result<()> {
+        let set = |value: &mut Option<String>, key: &str| {
+            if let Some(v) = var(key) {
+                *value = Some(v);
+            }
+        };
+        set(&mut self.locale, "TDT_LOCALE");
+        set(&mut self.file, "TDT_FILE");
+        set(&mut self.archive, "TDT_ARCHIVE");
+        set(&mut self.date_format, "TDT_DATE_FORMAT");
+        set(&mut self.add.priority, "TDT_PRIORITY");
+        set(&mut self.add.due, "TDT_DUE");
+        set(&mut self.list.mode, "TDT_MODE");
+        set(&mut self.list.sort, "TDT_SORT");
+        if let Some(backup) = var("TDT_BACKUP") {
+            self.backup = Some(!matches!(backup.as_str(), "0" | "false"));
+        }
+        if let Some(levels) = var("TDT_PRIORITY_LEVELS") {
+            let levels = levels
+                .parse()
+                .map_err(|_| invalid("priority.levels", &levels, &levels_reason()))?;
+            self.priority.levels = Some(levels);
+        }
+        for key in COLOR_KEYS {
+            if let Some(color) = var(&format!("TDT_COLOR_{}", key.to_uppercase())) {
+                self.colors.insert(key.to_string(), color);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// 任务各部分的颜色
+pub struct Theme {
+    /// 已完成和已移除的状态标记
+    pub state: Color,
+    /// 进行中的状态标记
+    pub in_progress: Color,
+    /// 等待中的状态标记
+    pub waiting: Color,
+    pub priority: Color,
+    /// 创建日期
+    pub created: Color,
+    /// 未到期的截止日期
+    pub due: Color,
+    /// 已过期的截止日期
+    pub overdue: Color,
+    /// 完成日期，以及按时完成的任务的截止日期
+    pub done: Color,
+    /// 逾期完成的任务的截止日期
+    pub late: Color,
+    /// 重复规则
+    pub recurrence: Color,
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Self {
+            state: Color::Green,
+            in_progress: Color::BrightGreen,
+            waiting: Color::BrightBlack,
+            priority: Color::Yellow,
+            created: Color::Blue,
+            due: Color::Cyan,
+            overdue: Color::Red,
+            done: Color::Green,
+            late: Color::Magenta,
+            recurrence: Color::Magenta,
+        }
+    }
+}
+
+impl Theme {
+    fn set(&mut self, key: &str, color: Color) {
+        match key {
+            "state" => self.state = color,
+            "in_progress" => self.in_progress = color,
+            "waiting" => self.waiting = color,
+            "priority" => self.priority = color,
+            "created" => self.created = color,
+            "due" => self.due = color,
+            "overdue" => self.overdue = color,
+            "done" => self.done = color,
+            "late" => self.late = color,
+            "recurrence" => self.recurrence = color,
+            _ => unreachable!(),
+        }
+    }
+
+    /// 状态标记的颜色
+    pub fn state_color(&self, state: &State) -> Color {
+        match state {
+            State::InProgress => self.in_progress,
+            State::Waiting => self.waiting,
+            _ => self.state,
+        }
+    }
+}
+
+/// 合并配置文件和环境变量后的配置，命令行参数优先于这里的各项
+pub struct Config {
+    /// 界面语言，如 `en`，未设置时按 `LANG` 等环境变量选择
+    pub locale: Option<String>,
+    /// 默认的任务清单文件
+    pub file: Option<PathBuf>,
+    /// 默认的归档文件
+    pub archive: Option<PathBuf>,
+    /// 显示日期的 strftime 格式，写入文件时总是使用 `YYYY-MM-DD`
+    pub date_format: Option<String>,
+    /// 每次写入前是否将上一版本保留为 `.bak` 文件
+    pub backup: bool,
+    /// 启用的优先级等级数，默认为 26，即 A 到 Z
+    pub priority_levels: usize,
+    /// `add` 的默认优先级
+    pub priority: Priority,
+    /// `add` 的默认截止日期
+    pub due_to: Option<Date>,
+    /// 默认的显示模式
+    pub mode: DisplayMode,
+    /// 默认的排序方式
+    pub sort_by: Option<String>,
+    pub theme: Theme,
+    /// 命令别名，如 `ls = "list --mode pcr"`
+    pub aliases: HashMap<String, String>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            locale: None,
+            file: None,
+            archive: None,
+            date_format: None,
+            backup: false,
+            priority_levels: MAX_LEVELS,
+            priority: Priority::default(),
+            due_to: None,
+            mode: DisplayMode::OPEN,
+            sort_by: None,
+            theme: Theme::default(),
+            aliases: HashMap::new(),
+        }
+    }
+}
+
+impl Config {
+    /// 读取配置文件，再以 `TDT_*` 环境变量覆盖其中的各项
+    pub fn load() -> Result<Self> {
+        let mut raw = match config_path() {
+            Some(path) if path.exists() => {
+                let text = fs::read_to_string(&path)?;
+                toml::from_str(&text).map_err(|e| {
+                    let line = e
+                        .span()
+                        .map_or(1, |span| text[..span.start].matches('\n').count() + 1);
+                    Error::InvalidConfig(t!("config.at_line", path.display(), line, e.message()))
+                })?
+            }
+            _ => RawConfig::default(),
+        };
+
+        raw.override_with(env_var)?;
+        Self::resolve(raw)
+    }
+
+    fn resolve(raw: RawConfig) -> Result<Self> {
+        let mut config = Config {
+            locale: raw.locale,
+            file: raw.file.as_deref().map(expand_home),
+            archive: raw.archive.as_deref().map(expand_home),
+            backup: raw.backup.unwrap_or_default(),
+            ..Config::default()
+        };
+
+        if let Some(format) = raw.date_format {
+            if date::today().format(&format).is_none() {
+                return Err(invalid(
+                    "date_format",
+                    &format,
+                    t!("config.invalid_date_format"),
+                ));
+            }
+            config.date_format = Some(format);
+        }
+        if let Some(levels) = raw.priority.levels {
+            if !(1..=MAX_LEVELS).contains(&levels) {
+                return Err(invalid(
+                    "priority.levels",
+                    &levels.to_string(),
+                    &levels_reason(),
+                ));
+            }
+            config.priority_levels = levels;
+        }
+        if let Some(priority) = parse("add.priority", raw.add.priority)? {
+            config.priority = config
+                .check_priority(priority)
+                .map_err(|e| invalid("add.priority", &priority.to_string(), &e.to_string()))?;
+        }
+        config.due_to = parse("add.due", raw.add.due)?;
+        if let Some(mode) = parse("list.mode", raw.list.mode)? {
+            config.mode = mode;
+        }
+        if let Some(sort) = raw.list.sort {
+            if !matches!(sort.as_str(), "p" | "priority" | "d" | "due") {
+                return Err(invalid("list.sort", &sort, t!("config.sort_values")));
+            }
+            config.sort_by = Some(sort);
+        }
+
+        for (key, value) in raw.colors {
+            if !COLOR_KEYS.contains(&key.as_str()) {
+                return Err(Error::InvalidConfig(t!("config.unknown_color", key)));
+            }
+            let color = Color::from_str(&value).map_err(|_| {
+                invalid(
+                    &format!("colors.{}", key),
+                    &value,
+                    t!("config.invalid_color"),
+                )
+            })?;
+            config.theme.set(&key, color);
+        }
+
+        for (name, command) in &raw.alias {
+            if shlex::split(command).is_none_or(|args| args.is_empty()) {
+                return Err(invalid(
+                    &format!("alias.{}", name),
+                    command,
+                    t!("config.invalid_command"),
+                ));
+            }
+        }
+        config.aliases = raw.alias;
+
+        Ok(config)
+    }
+
+    /// 检查优先级是否已启用，没有优先级总是可用
+    pub fn check_priority(&self, priority: Priority) -> Result<Priority> {
+        match priority.level() {
+            Some(level) if level > self.priority_levels => Err(Error::InvalidPriority(t!(
+                "config.priority_disabled",
+                priority,
+                self.priority_range()
+            ))),
+            _ => Ok(priority),
+        }
+    }
+
+    /// 已启用的优先级，如 `A-Z`
+    pub fn priority_range(&self) -> String {
+        match Priority::levels(self.priority_levels).last() {
+            Some(Priority::A) | None => Priority::A.to_string(),
+            Some(last) => format!("A-{}", last),
+        }
+    }
+
+    /// 将命令行中的别名展开为相应的子命令和参数，与内置子命令同名的别名不生效
+    pub fn expand_alias(&self, mut args: Vec<OsString>, builtin: &[&str]) -> Vec<OsString> {
+        let mut i = 1;
+        while let Some(arg) = args.get(i).and_then(|arg| arg.to_str()) {
+            if arg == "--" {
+                return args;
+            } else if VALUE_OPTIONS.contains(&arg) {
+                i += 2;
+            } else if arg.starts_with('-') {
+                i += 1;
+            } else {
+                break;
+            }
+        }
+
+        let Some(name) = args.get(i).and_then(|arg| arg.to_str()) else {
+            return args;
+        };
+        if builtin.contains(&name) {
+            return args;
+        }
+        if let Some(command) = self.aliases.get(name) {
+            let expanded = shlex::split(command).unwrap_or_default();
+            args.splice(i..=i, expanded.into_iter().map(OsString::from));
+        }
+        args
+    }
+}
+
+/// 初始化全局配置，只在启动时调用一次
+pub fn init(config: Config) {
+    _ = CONFIG.set(config);
+}
+
+/// 全局配置，未初始化时为默认配置
+pub fn get() -> &'static Config {
+    CONFIG.get_or_init(Config::default)
+}
+
+/// 环境变量中的界面语言，`TDT_LOCALE` 优先于 `LC_ALL`、`LC_MESSAGES` 和 `LANG`
+pub fn env_locale() -> Option<String> {
+    env_var("TDT_LOCALE").or_else(i18n::env_locale)
+}
+
+/// 语言的消息目录：优先使用配置目录下的 `locales/<语言>.toml`，其次为内置目录，
+/// 都没有时使用英文；未指定语言时为默认的中文
+pub fn catalog(locale: Option<&str>) -> Result<Catalog> {
+    let Some(locale) = locale else {
+        return Ok(Catalog::builtin(i18n::DEFAULT_LOCALE).unwrap());
+    };
+    let builtin = || {
+        Catalog::builtin(locale)
+            .or_else(|| Catalog::builtin("en"))
+            .unwrap()
+    };
+
+    let dir = config_path().and_then(|path| Some(path.parent()?.join("locales")));
+    let custom = dir.and_then(|dir| {
+        [locale.replace('_', "-"), i18n::language(locale)]
+            .into_iter()
+            .map(|name| dir.join(format!("{}.toml", name)))
+            .find(|path| path.exists())
+    });
+    match custom {
+        Some(path) => {
+            let text = fs::read_to_string(&path)?;
+            let catalog = Catalog::parse(locale, &text).map_err(|e| match e {
+                Error::InvalidConfig(message) => {
+                    Error::InvalidConfig(format!("{}: {}", path.display(), message))
+                }
+                e => e,
+            })?;
+            Ok(catalog.with_fallback(builtin()))
+        }
+        None => Ok(builtin()),
+    }
+}
+
+/// `$TDT_CONFIG`，否则为 `$XDG_CONFIG_HOME/tdt/config.toml` 或 `~/.config/tdt/config.toml`
+pub fn config_path() -> Option<PathBuf> {
+    if let Some(path) = env_var("TDT_CONFIG") {
+        return Some(expand_home(&path));
+    }
+    let dir = match env_var("XDG_CONFIG_HOME") {
+        Some(dir) => PathBuf::from(dir),
+        None => home::home_dir()?.join(".config"),
+    };
+    Some(dir.join("tdt").join("config.toml"))
+}
+
+fn env_var(key: &str) -> Option<String> {
+    env::var(key).ok().filter(|value| !value.is_empty())
+}
+
+/// 将开头的 `~/` 展开为用户主目录
+fn expand_home(path: &str) -> PathBuf {
+    match (path.strip_prefix("~/"), home::home_dir()) {
+        (Some(rest), Some(home)) => home.join(rest),
+        _ => PathBuf::from(path),
+    }
+}
+
+fn parse<T>(key: &str, value: Option<String>) -> Result<Option<T>>
+where
+    T: FromStr,
+    T::Err: fmt::Display,
+{
+    value
+        .map(|value| {
+            value
+                .parse()
+                .map_err(|e: T::Err| invalid(key, &value, &e.to_string()))
+        })
+        .transpose()
+}
+
+fn levels_reason() -> String {
+    t!("config.priority_levels", MAX_LEVELS)
+}
+
+fn invalid(key: &str, value: &str, reason: &str) -> Error {
+    Error::InvalidConfig(format!("{} = \"{}\": {}", key, value, reason))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn resolve(toml: &str, vars: &[(&str, &str)]) -> Result<Config> {
+        let mut raw: RawConfig = toml::from_str(toml).unwrap();
+        let vars: HashMap<String, String> = vars
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect();
+        raw.override_with(|key| vars.get(key).cloned())?;
+        Config::resolve(raw)
+    }
+
+    fn args(args: &[&str]) -> Vec<OsString> {
+        args.iter().map(OsString::from).collect()
+    }
+
+    #[test]
+    fn env_overrides_file() {
+        let toml = r#"
+            file = "/file/todo.txt"
+            backup = true
+            [add]
+            priority = "B"
+            [list]
+            sort = "due"
+            [priority]
+            levels = 5
+        "#;
+        let config = resolve(toml, &[]).unwrap();
+        assert_eq!(config.file, Some(PathBuf::from("/file/todo.txt")));
+        assert!(config.backup);
+        assert_eq!(config.priority.as_str(), "B");
+
+        let config = resolve(
+            toml,
+            &[
+                ("TDT_FILE", "/env/todo.txt"),
+                ("TDT_BACKUP", "0"),
+                ("TDT_PRIORITY", "C"),
+                ("TDT_PRIORITY_LEVELS", "3"),
+            ],
+        )
+        .unwrap();
+        assert_eq!(config.file, Some(PathBuf::from("/env/todo.txt")));
+        assert!(!config.backup);
+        assert_eq!(config.priority.as_str(), "C");
+        assert_eq!(config.priority_levels, 3);
+        // 未被覆盖的项保持配置文件中的值
+        assert_eq!(config.sort_by.as_deref(), Some("due"));
+
+        // 环境变量中的值同样检查
+        assert!(resolve(toml, &[("TDT_PRIORITY", "D"), ("TDT_PRIORITY_LEVELS", "3")]).is_err());
+        assert!(resolve(toml, &[("TDT_PRIORITY_LEVELS", "27")]).is_err());
+        assert!(resolve("", &[("TDT_SORT", "name")]).is_err());
+    }
+
+    #[test]
+    fn aliases_expand_once() {
+        let toml = r#"
+            [alias]
+            ls = "list --mode pcr"
+            l = "ls -s p"
+            loop = "loop again"
+            list = "add shadowed"
+        "#;
+        let config = resolve(toml, &[]).unwrap();
+        let builtin = ["list", "add"];
+        let expand = |input: &[&str]| config.expand_alias(args(input), &builtin);
+
+        assert_eq!(
+            expand(&["tdt", "-f", "x.txt", "ls", "+work"]),
+            args(&["tdt", "-f", "x.txt", "list", "--mode", "pcr", "+work"])
+        );
+        // 别名中的别名和引用自身的别名只展开一次，不会无限展开
+        assert_eq!(expand(&["tdt", "l"]), args(&["tdt", "ls", "-s", "p"]));
+        assert_eq!(expand(&["tdt", "loop"]), args(&["tdt", "loop", "again"]));
+        // 与内置子命令同名的别名和 `--` 之后的参数不展开
+        assert_eq!(expand(&["tdt", "list"]), args(&["tdt", "list"]));
+        assert_eq!(expand(&["tdt", "--", "ls"]), args(&["tdt", "--", "ls"]));
+
+        assert!(resolve("[alias]\nbad = \"'unclosed\"", &[]).is_err());
+    }
+
+    #[test]
+    fn check_priority_respects_levels() {
+        let config = resolve("[priority]\nlevels = 3", &[]).unwrap();
+        assert!(config.check_priority(Priority::A).is_ok());
+        assert!(config.check_priority(Priority::C).is_ok());
+        assert!(config.check_priority(Priority::NONE).is_ok());
+        assert!(matches!(
+            config.check_priority("D".parse().unwrap()),
+            Err(Error::InvalidPriority(_))
+        ));
+        assert_eq!(config.priority_range(), "A-C");
+
+        let config = Config::default();
+        assert!(config.check_priority("Z".parse().unwrap()).is_ok());
+        assert_eq!(config.priority_range(), "A-Z");
+        let config = resolve("[priority]\nlevels = 1", &[]).unwrap();
+        assert_eq!(config.priority_range(), "A");
+        assert!(config.check_priority(Priority::B).is_err());
+    }
+}
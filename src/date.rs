@@ -1,23 +1,29 @@
-use chrono::NaiveDate;
-use std::io::{Error, ErrorKind};
+use chrono::{Datelike, NaiveDate, Weekday};
+use std::fmt::Write;
 use std::str::FromStr;
 
+use crate::error::Error;
+
+/// 不含时间的日期
 #[derive(Clone, Copy, PartialEq, Eq)]
 pub struct Date(NaiveDate);
 
+/// 除 `YYYY-MM-DD` 外，还接受相对于今天的日期：
+/// `today`/`tomorrow`/`yesterday`、`+3d`/`2w`/`1m`/`-1y`、
+/// 星期 `mon`/`next friday`（今天之后最近的一天）、`eow`（本周日）、`eom`（本月最后一天）
 impl FromStr for Date {
-    type Err = std::io::Error;
+    type Err = Error;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        NaiveDate::parse_from_str(s, "%Y-%m-%d")
-            .map(Date)
-            .map_err(|e| Error::new(ErrorKind::InvalidData, e))
+        Date::parse_exact(s)
+            .or_else(|| Date::parse_relative(s, today()))
+            .ok_or_else(|| Error::InvalidDate(s.to_string()))
     }
 }
 
 impl PartialOrd for Date {
     fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
-        Some(self.0.cmp(&other.0))
+        Some(self.cmp(other))
     }
 }
 
@@ -28,15 +34,199 @@ impl Ord for Date {
 }
 
 impl Date {
+    /// 只接受 `YYYY-MM-DD` 格式，用于读取任务清单
+    pub fn parse_exact(s: &str) -> Option<Date> {
+        NaiveDate::parse_from_str(s, "%Y-%m-%d").ok().map(Date)
+    }
+
+    pub fn parse_relative(s: &str, today: Date) -> Option<Date> {
+        let s = s.trim().to_lowercase();
+        let s = s.strip_prefix("next ").unwrap_or(&s);
+        match s {
+            "today" | "tod" | "今天" => return Some(today),
+            "tomorrow" | "tom" | "明天" => return today.checked_add_days(1),
+            "yesterday" | "昨天" => return today.checked_add_days(-1),
+            "后天" => return today.checked_add_days(2),
+            "eow" => {
+                let days = 6 - today.0.weekday().num_days_from_monday();
+                return today.checked_add_days(days.into());
+            }
+            "eom" => {
+                let first = today.0.with_day(1)?;
+                let next = first.checked_add_months(chrono::Months::new(1))?;
+                return Date(next).checked_add_days(-1);
+            }
+            _ => (),
+        }
+        if let Ok(weekday) = s.parse::<Weekday>() {
+            let from = today.0.weekday().num_days_from_monday();
+            let to = weekday.num_days_from_monday();
+            let days = (to + 7 - from - 1) % 7 + 1;
+            return today.checked_add_days(days.into());
+        }
+
+        // 偏移量，如 +3d、2w、-1m
+        let (sign, rest) = match s.strip_prefix('-') {
+            Some(rest) => (-1, rest),
+            None => (1, s.strip_prefix('+').unwrap_or(s)),
+        };
+        let unit = rest.chars().last()?;
+        let amount = &rest[..rest.len() - unit.len_utf8()];
+        // 符号只能有一个，`i64` 的解析还会接受开头的 `+` 和 `-`
+        if !amount.bytes().all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+        let amount: i64 = amount.parse().ok()?;
+        let amount = amount.checked_mul(sign)?;
+        match unit {
+            'd' => today.checked_add_days(amount),
+            'w' => today.checked_add_days(amount.checked_mul(7)?),
+            'm' => today.shift_months(amount),
+            'y' => today.shift_months(amount.checked_mul(12)?),
+            _ => None,
+        }
+    }
+
     pub fn fmt(&self) -> String {
         self.0.format("%Y-%m-%d").to_string()
     }
 
+    /// 按 strftime 格式显示，如 `%m/%d`，格式无效时返回 None
+    pub fn format(&self, format: &str) -> Option<String> {
+        let mut s = String::new();
+        write!(s, "{}", self.0.format(format)).ok()?;
+        Some(s)
+    }
+
     pub fn is_over(&self) -> bool {
         today().0 > self.0
     }
+
+    /// 加上若干天，超出可表示的日期范围时返回 None
+    pub fn checked_add_days(&self, days: i64) -> Option<Date> {
+        let delta = chrono::Duration::try_days(days)?;
+        self.0.checked_add_signed(delta).map(Date)
+    }
+
+    /// 加上若干个月，超出当月天数时取当月最后一天，超出可表示的日期范围时返回 None
+    pub fn checked_add_months(&self, months: u32) -> Option<Date> {
+        self.0
+            .checked_add_months(chrono::Months::new(months))
+            .map(Date)
+    }
+
+    fn shift_months(&self, months: i64) -> Option<Date> {
+        let delta = chrono::Months::new(u32::try_from(months.unsigned_abs()).ok()?);
+        if months < 0 {
+            self.0.checked_sub_months(delta).map(Date)
+        } else {
+            self.0.checked_add_months(delta).map(Date)
+        }
+    }
 }
 
+/// 本地时区的今天
 pub fn today() -> Date {
     Date(chrono::Local::now().date_naive())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 2024-01-31 是星期三
+    const TODAY: &str = "2024-01-31";
+
+    fn relative(s: &str, today: &str) -> Option<String> {
+        let today = Date::parse_exact(today).unwrap();
+        Date::parse_relative(s, today).map(|date| date.fmt())
+    }
+
+    fn assert_relative(s: &str, today: &str, expected: &str) {
+        assert_eq!(relative(s, today).as_deref(), Some(expected), "{}", s);
+    }
+
+    #[test]
+    fn keywords() {
+        assert_relative("today", TODAY, "2024-01-31");
+        assert_relative("Tomorrow", TODAY, "2024-02-01");
+        assert_relative("yesterday", TODAY, "2024-01-30");
+        assert_relative("后天", TODAY, "2024-02-02");
+    }
+
+    #[test]
+    fn offsets() {
+        assert_relative("+3d", TODAY, "2024-02-03");
+        assert_relative("3d", TODAY, "2024-02-03");
+        assert_relative("-1d", TODAY, "2024-01-30");
+        assert_relative("2w", TODAY, "2024-02-14");
+        // 超出当月天数时取当月最后一天
+        assert_relative("1m", TODAY, "2024-02-29");
+        assert_relative("-1m", "2024-03-31", "2024-02-29");
+        assert_relative("1y", "2024-02-29", "2025-02-28");
+    }
+
+    #[test]
+    fn end_of_week_and_month() {
+        assert_relative("eow", TODAY, "2024-02-04");
+        assert_relative("eow", "2024-02-04", "2024-02-04");
+        assert_relative("eom", "2024-02-10", "2024-02-29");
+        assert_relative("eom", "2024-12-15", "2024-12-31");
+    }
+
+    #[test]
+    fn weekdays_are_always_in_the_future() {
+        assert_relative("fri", TODAY, "2024-02-02");
+        assert_relative("next friday", TODAY, "2024-02-02");
+        assert_relative("wed", TODAY, "2024-02-07");
+        assert_relative("tue", TODAY, "2024-02-06");
+    }
+
+    #[test]
+    fn invalid() {
+        for s in [
+            "",
+            "d",
+            "+d",
+            "3x",
+            "3 d",
+            "someday",
+            "2024-13-01",
+            "++3d",
+            "+-3d",
+            "--3d",
+        ] {
+            assert_eq!(relative(s, TODAY), None, "{}", s);
+        }
+    }
+
+    #[test]
+    fn overflow_is_rejected() {
+        for s in [
+            "+99999999d",
+            "-99999999d",
+            "+9999999999w",
+            "+9223372036854775807w",
+            "-9223372036854775808d",
+            "99999999999999999999d",
+            "+9999999m",
+            "+5000000000m",
+            "+9999999y",
+            "+768614336404564651y",
+        ] {
+            assert_eq!(relative(s, TODAY), None, "{}", s);
+        }
+        // 在可表示的最后一天上计算也不会溢出
+        let max = Date(NaiveDate::MAX);
+        for s in ["tomorrow", "+1d", "1m", "eow", "eom", "mon"] {
+            assert!(Date::parse_relative(s, max).is_none(), "{}", s);
+        }
+        assert!(Date::parse_relative("-1d", max).is_some());
+    }
+
+    #[test]
+    fn from_str_rejects_overflow() {
+        assert!("+99999999d".parse::<Date>().is_err());
+        assert!("2024-02-30".parse::<Date>().is_err());
+    }
+}
@@ -0,0 +1,120 @@
+//! 任务之间的依赖关系
+//!
+//! 任务的 `deps` 为它的前置任务，前置任务都已完成之前，该任务处于阻塞状态。
+//! 已移除的前置任务和不在任务清单中的前置任务（已删除或已归档）不再阻塞
+
+use std::collections::{HashMap, HashSet};
+
+use crate::state::State;
+use crate::task::Task;
+
+/// 前置任务已完成或已移除时不再阻塞依赖它的任务
+fn resolved(task: &Task) -> bool {
+    matches!(task.state, State::Completed | State::Removed)
+}
+
+/// 任务尚未完成的前置任务的编号
+pub fn unfinished(tasks: &[Task], task: &Task) -> Vec<usize> {
+    task.deps
+        .iter()
+        .copied()
+        .filter(|dep| {
+            tasks
+                .iter()
+                .any(|other| other.id == *dep && !resolved(other))
+        })
+        .collect()
+}
+
+/// 处于阻塞状态的任务的编号
+pub fn blocked(tasks: &[Task]) -> HashSet<usize> {
+    let unfinished: HashSet<usize> = tasks
+        .iter()
+        .filter(|task| !resolved(task))
+        .map(|task| task.id)
+        .collect();
+    tasks
+        .iter()
+        .filter(|task| task.deps.iter().any(|dep| unfinished.contains(dep)))
+        .map(|task| task.id)
+        .collect()
+}
+
+/// 经过指定任务的依赖环，返回环上的任务编号，首尾为该任务，如 `[1, 3, 1]`
+pub fn find_cycle(tasks: &[Task], id: usize) -> Option<Vec<usize>> {
+    let deps: HashMap<usize, &[usize]> = tasks
+        .iter()
+        .map(|task| (task.id, task.deps.as_slice()))
+        .collect();
+    let mut path = vec![id];
+    let mut visited = HashSet::new();
+    search(id, &deps, &mut path, &mut visited).then_some(path)
+}
+
+/// 沿依赖深度优先地查找回到 `path[0]` 的路径，找到时 `path` 即为该环
+fn search(
+    id: usize,
+    deps: &HashMap<usize, &[usize]>,
+    path: &mut Vec<usize>,
+    visited: &mut HashSet<usize>,
+) -> bool {
+    for &dep in deps.get(&id).copied().unwrap_or_default() {
+        if dep == path[0] {
+            path.push(dep);
+            return true;
+        }
+        if visited.insert(dep) {
+            path.push(dep);
+            if search(dep, deps, path, visited) {
+                return true;
+            }
+            path.pop();
+        }
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::priority::Priority;
+
+    fn task(id: usize, state: State, deps: &[usize]) -> Task {
+        let mut task = Task::new(Priority::NONE, format!("task {}", id), None);
+        task.id = id;
+        task.state = state;
+        task.deps = deps.to_vec();
+        task
+    }
+
+    #[test]
+    fn removed_prerequisites_do_not_block() {
+        let tasks = vec![
+            task(1, State::Completed, &[]),
+            task(2, State::Removed, &[]),
+            task(3, State::Waiting, &[]),
+            task(4, State::Pending, &[1, 2]),
+            task(5, State::Pending, &[2, 3, 9]),
+        ];
+        assert!(unfinished(&tasks, &tasks[3]).is_empty());
+        assert_eq!(unfinished(&tasks, &tasks[4]), vec![3]);
+        assert_eq!(blocked(&tasks), HashSet::from([5]));
+    }
+
+    #[test]
+    fn cycles_through_the_given_task() {
+        let tasks = vec![
+            task(1, State::Pending, &[2]),
+            task(2, State::Pending, &[3, 4]),
+            task(3, State::Pending, &[]),
+            task(4, State::Pending, &[1]),
+            task(5, State::Pending, &[5]),
+        ];
+        assert_eq!(find_cycle(&tasks, 1), Some(vec![1, 2, 4, 1]));
+        assert_eq!(find_cycle(&tasks, 4), Some(vec![4, 1, 2, 4]));
+        assert_eq!(find_cycle(&tasks, 5), Some(vec![5, 5]));
+        // 被环上的任务依赖但不在环上
+        assert_eq!(find_cycle(&tasks, 3), None);
+        assert_eq!(find_cycle(&tasks, 9), None);
+    }
+}
@@ -0,0 +1,354 @@
+use colored::Colorize;
+use std::collections::hash_map::RandomState;
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::fs::{self, File, OpenOptions};
+use std::hash::{BuildHasher, Hasher};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process::{self, Command};
+use std::time::SystemTime;
+use todotxt::parser::{LineParser, Repairer};
+use todotxt::{date, t, Action, Change, Codec, Error, Result, State, Task, TodoList};
+
+use crate::cli::{DisplayMode, TaskConf};
+use crate::tasks;
+
+/// 编辑结束后删除的临时文件
+struct TempFile(PathBuf);
+
+impl TempFile {
+    /// 在临时目录中新建只有当前用户可读写的文件，文件名含随机部分，
+    /// 不会打开已存在的文件或符号链接，重名时换一个文件名重试
+    fn create(contents: &str) -> Result<Self> {
+        let mut attempts = 0;
+        loop {
+            let name = format!("tdt-edit-{}-{:016x}.txt", process::id(), random());
+            let path = env::temp_dir().join(name);
+            match create_new(&path) {
+                Ok(mut file) => {
+                    let temp = Self(path);
+                    file.write_all(contents.as_bytes())?;
+                    return Ok(temp);
+                }
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists && attempts < 16 => {
+                    attempts += 1
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        _ = fs::remove_file(&self.0);
+    }
+}
+
+/// 无法解析的行：(行号, 列号, 原因)
+type EditError = (usize, usize, String);
+
+/// 在编辑器中编辑选出的任务，保存后按编号应用新增、修改和删除
+pub fn edit_tasks(list: &mut TodoList, mode: &DisplayMode, conf: &TaskConf) -> Result<()> {
+    let query = conf.query(list.tasks())?;
+    // 与 list 的显示顺序相反，使最重要的任务在最上方
+    let selected: Vec<Task> =
+        tasks::filter_tasks(list.tasks(), mode, &query, conf.sort_by.as_deref())
+            .into_iter()
+            .rev()
+            .cloned()
+            .collect();
+    let ids: HashSet<usize> = selected.iter().map(|task| task.id).collect();
+
+    let lines: Vec<String> = selected.iter().map(Task::stringify).collect();
+    let file = TempFile::create(&format!("{}{}\n", t!("editor.header"), lines.join("\n")))?;
+
+    let edited = loop {
+        open_editor(&file.0)?;
+        match parse_edited(&fs::read_to_string(&file.0)?, &ids) {
+            Ok(edited) => break edited,
+            Err(errors) => {
+                for (line_no, column, reason) in errors {
+                    eprintln!(
+                        "{} {}",
+                        "==>".red(),
+                        t!("tasks.line_error", line_no, column, reason)
+                    );
+                }
+                println!("{} {}", "==>".cyan(), t!("editor.reedit"));
+                tasks::prompt_input()?;
+                let mut input = String::new();
+                io::stdin().read_line(&mut input)?;
+                if input.trim().eq_ignore_ascii_case("n") {
+                    println!("{} {}", "==>".yellow(), t!("editor.discarded"));
+                    return Ok(());
+                }
+            }
+        }
+    };
+
+    // 保存了空文件时多半是误操作，删除全部任务前须明确确认
+    if edited.is_empty() && !selected.is_empty() {
+        println!(
+            "{} {}",
+            "==>".yellow(),
+            t!("editor.delete_all", selected.len())
+        );
+        tasks::prompt_input()?;
+        let mut input = String::new();
+        io::stdin().read_line(&mut input)?;
+        if !input.trim().eq_ignore_ascii_case("y") {
+            println!("{} {}", "==>".yellow(), t!("editor.discarded"));
+            return Ok(());
+        }
+    }
+
+    let changes = diff(&selected, edited);
+    if changes.is_empty() {
+        println!("{} {}", "==>".green(), t!("editor.unchanged"));
+        return Ok(());
+    }
+    let count = |f: fn(&Change) -> bool| changes.iter().filter(|change| f(change)).count();
+    let added = count(|change| change.before.is_none());
+    let deleted = count(|change| change.after.is_none());
+    let modified = changes.len() - added - deleted;
+
+    tasks::commit_changes(list, Action::Edit, changes)?;
+    println!(
+        "{} {}",
+        "==>".green(),
+        t!("editor.summary", added, modified, deleted)
+    );
+    Ok(())
+}
+
+/// 依次使用 `$VISUAL`、`$EDITOR` 和 `vi`，编辑器可以带参数，如 `code --wait`，
+/// 按 shell 的规则拆分，路径中有空格时可加引号
+fn open_editor(path: &Path) -> Result<()> {
+    let editor = ["VISUAL", "EDITOR"]
+        .iter()
+        .filter_map(|key| env::var(key).ok())
+        .find(|editor| !editor.trim().is_empty())
+        .unwrap_or_else(|| "vi".to_string());
+    let parts = shlex::split(&editor)
+        .filter(|parts| !parts.is_empty())
+        .ok_or_else(|| Error::InvalidInput(t!("editor.invalid_command", editor)))?;
+    let (program, args) = parts.split_first().unwrap();
+
+    let status = Command::new(program)
+        .args(args)
+        .arg(path)
+        .status()
+        .map_err(|e| Error::InvalidInput(t!("editor.cannot_start", program, e)))?;
+    if !status.success() {
+        return Err(Error::InvalidInput(t!("editor.failed", status)));
+    }
+    Ok(())
+}
+
+/// 解析编辑后的内容，新任务无法解析时尝试补上优先级和创建日期
+fn parse_edited(
+    text: &str,
+    ids: &HashSet<usize>,
+) -> std::result::Result<Vec<Task>, Vec<EditError>> {
+    let parser = LineParser::new();
+    let repairer = Repairer::new(Codec::Native);
+    let mut tasks = Vec::new();
+    let mut errors = Vec::new();
+    let mut seen = HashSet::new();
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let task = match parser.parse_line(line) {
+            Ok(task) => task,
+            Err(e) => match repairer.repair_line(line).filter(|task| task.id == 0) {
+                // 新任务省略创建日期时以今天为创建日期
+                Some(mut task) => {
+                    task.created_at.get_or_insert_with(date::today);
+                    task
+                }
+                None => {
+                    errors.push((i + 1, e.column, e.error.to_string()));
+                    continue;
+                }
+            },
+        };
+        if task.id != 0 && !ids.contains(&task.id) {
+            errors.push((i + 1, 1, t!("editor.out_of_scope", task.id)));
+        } else if task.id != 0 && !seen.insert(task.id) {
+            errors.push((i + 1, 1, t!("editor.duplicate", task.id)));
+        } else {
+            tasks.push(task);
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(tasks)
+    } else {
+        Err(errors)
+    }
+}
+
+/// 对比编辑前后的任务：消失的任务被删除，内容改变的任务被修改，没有编号的任务为新增；
+/// 在编辑器中完成的重复任务与其他方式完成时一样添加下一次任务
+fn diff(selected: &[Task], edited: Vec<Task>) -> Vec<Change> {
+    let mut added = Vec::new();
+    let mut edited_by_id = HashMap::new();
+    for task in edited {
+        if task.id == 0 {
+            added.push(Change::add(task));
+        } else {
+            edited_by_id.insert(task.id, task);
+        }
+    }
+
+    let mut changes = Vec::new();
+    for before in selected {
+        match edited_by_id.remove(&before.id) {
+            Some(mut after) if after.stringify() != before.stringify() => {
+                if after.state != State::Completed {
+                    changes.push(Change::update(before, after));
+                    continue;
+                }
+                // 在编辑器中标记为完成而未写完成日期时，以今天为完成日期
+                let next = match after.completed_at {
+                    None => after.complete(),
+                    Some(_) => after.next_recurrence(),
+                };
+                changes.push(Change::update(before, after));
+                if before.state != State::Completed {
+                    changes.extend(next.map(|next| Change::add_after(next, before.id)));
+                }
+            }
+            Some(_) => (),
+            None => changes.push(Change::delete(before)),
+        }
+    }
+    changes.extend(added);
+    changes
+}
+
+#[cfg(unix)]
+fn create_new(path: &Path) -> io::Result<File> {
+    use std::os::unix::fs::OpenOptionsExt;
+    OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(path)
+}
+
+#[cfg(not(unix))]
+fn create_new(path: &Path) -> io::Result<File> {
+    OpenOptions::new().write(true).create_new(true).open(path)
+}
+
+/// 临时文件名中的随机部分，由系统随机初始化的哈希键和当前时间生成
+fn random() -> u64 {
+    let mut hasher = RandomState::new().build_hasher();
+    if let Ok(elapsed) = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH) {
+        hasher.write_u128(elapsed.as_nanos());
+    }
+    hasher.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(line: &str) -> Task {
+        LineParser::new().parse_line(line).unwrap()
+    }
+
+    fn describe(changes: &[Change]) -> Vec<String> {
+        let stringify =
+            |task: &Option<Task>| task.as_ref().map_or("-".to_string(), Task::stringify);
+        changes
+            .iter()
+            .map(|change| {
+                format!(
+                    "{} => {}",
+                    stringify(&change.before),
+                    stringify(&change.after)
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn parse_edited_reports_errors_and_repairs_new_tasks() {
+        let ids = HashSet::from([1, 2]);
+        let text = "# comment\n\n[A] alpha (2024-01-01) (id:1)\nnew task\n";
+        let tasks = parse_edited(text, &ids).unwrap_or_else(|_| panic!());
+        assert_eq!(tasks.len(), 2);
+        assert_eq!(tasks[1].id, 0);
+        assert!(tasks[1].created_at.is_some());
+
+        let text =
+            "[A] alpha (2024-01-01) (id:1)\n[A] again (2024-01-01) (id:1)\n[B] b (-) (id:3)\n";
+        let Err(errors) = parse_edited(text, &ids) else {
+            panic!();
+        };
+        assert_eq!(
+            errors.iter().map(|(line, ..)| *line).collect::<Vec<_>>(),
+            vec![2, 3]
+        );
+    }
+
+    #[test]
+    fn diff_adds_modifies_and_deletes() {
+        let selected = vec![
+            parse("[A] alpha (2024-01-01) (id:1)"),
+            parse("[B] bravo (2024-01-01) (id:2)"),
+            parse("[C] charlie (2024-01-01) (id:3)"),
+        ];
+        let edited = vec![
+            parse("[A] alpha (2024-01-01) (id:1)"),
+            parse("[B] bravo edited (2024-01-01) (id:2)"),
+            parse("[D] delta (2024-01-01)"),
+        ];
+        assert_eq!(
+            describe(&diff(&selected, edited)),
+            vec![
+                "[B] bravo (2024-01-01) (id:2) => [B] bravo edited (2024-01-01) (id:2)",
+                "[C] charlie (2024-01-01) (id:3) => -",
+                "- => [D] delta (2024-01-01)",
+            ]
+        );
+    }
+
+    #[test]
+    fn completing_in_editor_adds_next_recurrence() {
+        let selected = vec![parse(
+            "[A] water (2024-01-01) (due:2024-01-08) (rec:+1w) (id:1)",
+        )];
+        let edited = vec![parse(
+            "✓ [A] water (2024-01-01) (due:2024-01-08) (rec:+1w) (id:1) (2024-01-09)",
+        )];
+        let changes = diff(&selected, edited);
+        assert_eq!(changes.len(), 2);
+        assert_eq!(
+            changes[0].after.as_ref().unwrap().stringify(),
+            "✓ [A] water (2024-01-01) (due:2024-01-08) (rec:+1w) (id:1) (2024-01-09)"
+        );
+        let next = changes[1].after.as_ref().unwrap();
+        assert_eq!(changes[1].requires, Some(1));
+        assert!(next.state == State::Pending);
+        assert_eq!(
+            next.due_to.map(|date| date.fmt()).as_deref(),
+            Some("2024-01-15")
+        );
+
+        // 已完成的任务再次修改时不再添加
+        let selected = vec![parse(
+            "✓ [A] water (2024-01-01) (due:2024-01-08) (rec:+1w) (id:1) (2024-01-09)",
+        )];
+        let edited = vec![parse(
+            "✓ [B] water (2024-01-01) (due:2024-01-08) (rec:+1w) (id:1) (2024-01-09)",
+        )];
+        assert_eq!(diff(&selected, edited).len(), 1);
+    }
+}
@@ -0,0 +1,125 @@
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+
+use crate::t;
+
+/// 库中所有操作的错误
+#[derive(Debug)]
+pub enum Error {
+    /// 读写文件时的错误
+    Io(io::Error),
+    /// 任务清单中无法解析的行，行号和列号从 1 开始
+    Parse {
+        path: PathBuf,
+        line: usize,
+        column: usize,
+        text: String,
+        cause: Box<Error>,
+    },
+    /// 不符合格式的单行任务
+    InvalidLine(String),
+    /// 任务中未知的 `key:value` 字段
+    UnknownField(String),
+    InvalidDate(String),
+    InvalidPriority(String),
+    InvalidRecurrence(String),
+    InvalidQuery(String),
+    /// 不是正整数的任务编号
+    InvalidId(String),
+    /// 任务清单中不存在或不可操作的任务编号
+    UnknownId(usize),
+    /// 无效的命令行输入
+    InvalidInput(String),
+    EmptyList,
+    /// 其他进程持有任务清单的锁
+    Locked,
+    InvalidJournal(String),
+    NothingToUndo,
+    NothingToRedo,
+    /// 任务在日志条目之后已被修改，无法撤销或重做
+    JournalConflict {
+        id: usize,
+        seq: usize,
+        undo: bool,
+    },
+    /// `check --fix` 无法修复任何一行
+    Unrepairable,
+    /// 配置文件或环境变量中的无效配置
+    InvalidConfig(String),
+    /// 无法解析的 iCalendar 文件
+    InvalidCalendar(String),
+    /// 依赖关系成环，首尾为同一个任务
+    DependencyCycle(Vec<usize>),
+    /// 要完成的任务还有未完成的前置任务
+    Blocked {
+        id: usize,
+        deps: Vec<usize>,
+    },
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "{}", e),
+            Self::Parse {
+                path,
+                line,
+                column,
+                cause,
+                ..
+            } => f.write_str(&t!("error.parse", path.display(), line, column, cause)),
+            Self::InvalidLine(reason) => f.write_str(reason),
+            Self::UnknownField(key) => f.write_str(&t!("error.unknown_field", key)),
+            Self::InvalidDate(s) => f.write_str(&t!("error.invalid_date", s)),
+            Self::InvalidPriority(s) => f.write_str(&t!("error.invalid_priority", s)),
+            Self::InvalidRecurrence(s) => f.write_str(&t!("error.invalid_recurrence", s)),
+            Self::InvalidQuery(message) => f.write_str(&t!("error.invalid_query", message)),
+            Self::InvalidId(s) => f.write_str(&t!("error.invalid_id", s)),
+            Self::UnknownId(id) => f.write_str(&t!("error.unknown_id", id)),
+            Self::InvalidInput(message) => f.write_str(message),
+            Self::EmptyList => f.write_str(t!("error.empty_list")),
+            Self::Locked => f.write_str(t!("error.locked")),
+            Self::InvalidJournal(message) => f.write_str(&t!("error.invalid_journal", message)),
+            Self::NothingToUndo => f.write_str(t!("error.nothing_to_undo")),
+            Self::NothingToRedo => f.write_str(t!("error.nothing_to_redo")),
+            Self::JournalConflict { id, seq, undo } => {
+                let key = if *undo {
+                    "error.undo_conflict"
+                } else {
+                    "error.redo_conflict"
+                };
+                f.write_str(&t!(key, id, seq))
+            }
+            Self::Unrepairable => f.write_str(t!("error.unrepairable")),
+            Self::InvalidConfig(message) => f.write_str(&t!("error.invalid_config", message)),
+            Self::InvalidCalendar(message) => f.write_str(&t!("error.invalid_calendar", message)),
+            Self::DependencyCycle(cycle) => {
+                let cycle: Vec<String> = cycle.iter().map(|id| id.to_string()).collect();
+                f.write_str(&t!("error.dependency_cycle", cycle.join(" -> ")))
+            }
+            Self::Blocked { id, deps } => {
+                let deps: Vec<String> = deps.iter().map(|id| id.to_string()).collect();
+                f.write_str(&t!("error.blocked", id, deps.join(", ")))
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io(e) => Some(e),
+            Self::Parse { cause, .. } => Some(cause.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Self::Io(e)
+    }
+}
@@ -0,0 +1,178 @@
+//! 用户可见文本的消息目录
+//!
+//! 内置 `zh-CN` 和 `en` 两种语言，目录为 TOML 文件，各节中的键展开为 `节.键`。
+//! 其他语言可以由调用方从文件读取后通过 [`set_catalog`] 设置，缺少的条目依次回退到
+//! 英文和中文目录。模板中的 `{}` 按顺序替换为参数，`{0}`、`{1}` 按位置替换
+
+use std::collections::HashMap;
+use std::fmt::{self, Display};
+use std::sync::{OnceLock, RwLock};
+
+use crate::error::{Error, Result};
+
+const ZH_CN: &str = include_str!("../locales/zh-CN.toml");
+const EN: &str = include_str!("../locales/en.toml");
+
+/// 未设置目录时使用的默认语言
+pub const DEFAULT_LOCALE: &str = "zh-CN";
+
+static CATALOG: RwLock<Option<&'static Catalog>> = RwLock::new(None);
+static DEFAULT: OnceLock<Catalog> = OnceLock::new();
+
+/// 一种语言的消息，缺少的条目从回退目录中查找
+pub struct Catalog {
+    locale: String,
+    messages: HashMap<String, String>,
+    fallback: Option<Box<Catalog>>,
+}
+
+impl Catalog {
+    /// 解析 TOML 格式的消息目录，出错时的消息不含位置
+    pub fn parse(locale: &str, text: &str) -> Result<Self> {
+        let table: toml::Table = text
+            .parse()
+            .map_err(|e: toml::de::Error| Error::InvalidConfig(e.message().trim().to_string()))?;
+        let mut messages = HashMap::new();
+        flatten(&mut messages, "", table);
+        Ok(Self {
+            locale: locale.to_string(),
+            messages,
+            fallback: None,
+        })
+    }
+
+    /// 内置的消息目录，`zh_CN.UTF-8`、`en-US` 等按语言匹配，没有该语言时返回 None
+    pub fn builtin(locale: &str) -> Option<Self> {
+        let catalog = match language(locale).as_str() {
+            "zh" => Self::parse("zh-CN", ZH_CN),
+            "en" => Self::parse("en", EN).map(|en| en.with_fallback(Self::zh_cn())),
+            _ => return None,
+        };
+        Some(catalog.expect("内置的消息目录应当有效"))
+    }
+
+    /// 设置回退目录，本目录及已有的回退目录中都缺少的条目从中查找
+    pub fn with_fallback(mut self, fallback: Catalog) -> Self {
+        self.fallback = Some(Box::new(match self.fallback.take() {
+            Some(inner) => inner.with_fallback(fallback),
+            None => fallback,
+        }));
+        self
+    }
+
+    pub fn locale(&self) -> &str {
+        &self.locale
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.messages
+            .get(key)
+            .map(String::as_str)
+            .or_else(|| self.fallback.as_ref()?.get(key))
+    }
+
+    fn zh_cn() -> Self {
+        Self::parse("zh-CN", ZH_CN).expect("内置的消息目录应当有效")
+    }
+}
+
+fn flatten(messages: &mut HashMap<String, String>, prefix: &str, table: toml::Table) {
+    for (key, value) in table {
+        let key = if prefix.is_empty() {
+            key
+        } else {
+            format!("{}.{}", prefix, key)
+        };
+        match value {
+            toml::Value::Table(table) => flatten(messages, &key, table),
+            toml::Value::String(s) => _ = messages.insert(key, s),
+            value => _ = messages.insert(key, value.to_string()),
+        }
+    }
+}
+
+/// 语言标签中的语言部分，如 `zh_CN.UTF-8` 为 `zh`
+pub fn language(locale: &str) -> String {
+    locale
+        .split(['_', '-', '.', '@'])
+        .next()
+        .unwrap_or_default()
+        .to_ascii_lowercase()
+}
+
+/// 从 `LC_ALL`、`LC_MESSAGES` 和 `LANG` 中依次取第一个非空的值，`C` 和 `POSIX` 视为未设置
+pub fn env_locale() -> Option<String> {
+    ["LC_ALL", "LC_MESSAGES", "LANG"]
+        .iter()
+        .filter_map(|key| std::env::var(key).ok())
+        .find(|value| !value.is_empty())
+        .filter(|value| !matches!(language(value).as_str(), "c" | "posix"))
+}
+
+/// 设置全局的消息目录，之后的 [`t`] 从中查找
+pub fn set_catalog(catalog: Catalog) {
+    // 目录在整个进程中有效，且只在启动时设置一两次，因此直接泄漏
+    let catalog: &'static Catalog = Box::leak(Box::new(catalog));
+    *CATALOG.write().unwrap() = Some(catalog);
+}
+
+/// 当前的消息目录，未设置时为默认语言
+pub fn catalog() -> &'static Catalog {
+    match *CATALOG.read().unwrap() {
+        Some(catalog) => catalog,
+        None => DEFAULT.get_or_init(Catalog::zh_cn),
+    }
+}
+
+/// 查找消息，不存在时返回键本身
+pub fn t(key: &'static str) -> &'static str {
+    catalog().get(key).unwrap_or(key)
+}
+
+/// 以参数替换模板中的 `{}` 和 `{N}`，`{{` 和 `}}` 为花括号本身
+pub fn format(template: &str, args: &[&dyn Display]) -> String {
+    let mut s = String::with_capacity(template.len());
+    let mut next = 0;
+    let mut rest = template;
+    while let Some(i) = rest.find(['{', '}']) {
+        s.push_str(&rest[..i]);
+        rest = &rest[i..];
+        if let Some(after) = rest.strip_prefix("{{") {
+            s.push('{');
+            rest = after;
+        } else if let Some(after) = rest.strip_prefix("}}") {
+            s.push('}');
+            rest = after;
+        } else if let Some(end) = rest.find('}').filter(|_| rest.starts_with('{')) {
+            let index = match &rest[1..end] {
+                "" => {
+                    next += 1;
+                    Some(next - 1)
+                }
+                n => n.parse().ok(),
+            };
+            match index.and_then(|i| args.get(i)) {
+                Some(arg) => _ = fmt::write(&mut s, format_args!("{}", arg)),
+                None => s.push_str(&rest[..=end]),
+            }
+            rest = &rest[end + 1..];
+        } else {
+            s.push_str(&rest[..1]);
+            rest = &rest[1..];
+        }
+    }
+    s.push_str(rest);
+    s
+}
+
+/// 查找并格式化消息：`t!("error.empty_list")` 返回 `&'static str`，
+/// `t!("error.invalid_date", s)` 返回替换参数后的 `String`
+#[macro_export]
+macro_rules! t {
+    ($key:expr) => {
+        $crate::i18n::t($key)
+    };
+    ($key:expr, $($arg:expr),+ $(,)?) => {
+        $crate::i18n::format($crate::i18n::t($key), &[$(&$arg as &dyn ::std::fmt::Display),+])
+    };
+}
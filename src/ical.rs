@@ -0,0 +1,464 @@
+//! iCalendar (RFC 5545) 中的 VTODO 与任务的相互转换
+//!
+//! 内容对应 SUMMARY，截止日期对应 DUE，标签对应 CATEGORIES。优先级 A/B/C 和没有优先级导出为
+//! PRIORITY 1/5/9/0，C 以下的优先级也导出为 9，导入时 1-4 为 A、5 为 B、6-9 为 C。状态对应 STATUS 的
+//! NEEDS-ACTION、IN-PROCESS、COMPLETED 和 CANCELLED，日历中没有等待中的状态，
+//! 导出为 NEEDS-ACTION
+
+use chrono::Utc;
+
+use crate::date::{self, Date};
+use crate::error::{Error, Result};
+use crate::parser;
+use crate::priority::Priority;
+use crate::state::State;
+use crate::t;
+use crate::task::Task;
+
+const PRODID: &str = "-//todotxt//tdt//EN";
+
+/// 内容行的最大长度（字节），超出的部分折行
+const LINE_LIMIT: usize = 75;
+
+/// 任务在日历中的 UID：从日历导入的任务沿用原来的 UID，其他任务由创建日期和编号生成
+pub fn uid(task: &Task) -> String {
+    match &task.uid {
+        Some(uid) => uid.clone(),
+        None => match task.created_at {
+            Some(created_at) => format!("{}-{}@tdt", created_at.fmt(), task.id),
+            None => format!("{}@tdt", task.id),
+        },
+    }
+}
+
+/// 将任务导出为包含若干个 VTODO 的 iCalendar 文本
+pub fn export<'a>(tasks: impl IntoIterator<Item = &'a Task>) -> String {
+    let stamp = Utc::now().format("%Y%m%dT%H%M%SZ").to_string();
+    let mut lines = vec![
+        "BEGIN:VCALENDAR".to_string(),
+        "VERSION:2.0".to_string(),
+        format!("PRODID:{}", PRODID),
+    ];
+    for task in tasks {
+        lines.push("BEGIN:VTODO".to_string());
+        lines.push(format!("UID:{}", escape(&uid(task))));
+        lines.push(format!("DTSTAMP:{}", stamp));
+        if let Some(created_at) = task.created_at {
+            lines.push(format!("CREATED:{}T000000Z", basic_date(created_at)));
+        }
+        lines.push(format!("SUMMARY:{}", escape(&task.content)));
+        if let Some(due_to) = task.due_to {
+            lines.push(format!("DUE;VALUE=DATE:{}", basic_date(due_to)));
+        }
+        lines.push(format!("PRIORITY:{}", to_priority(task.priority)));
+        lines.push(format!("STATUS:{}", to_status(&task.state)));
+        if let Some(completed_at) = task.completed_at.filter(|_| task.state == State::Completed) {
+            lines.push(format!("COMPLETED:{}T000000Z", basic_date(completed_at)));
+        }
+        if !task.tags.is_empty() {
+            let tags: Vec<String> = task.tags.iter().map(|tag| escape(tag)).collect();
+            lines.push(format!("CATEGORIES:{}", tags.join(",")));
+        }
+        lines.push("END:VTODO".to_string());
+    }
+    lines.push("END:VCALENDAR".to_string());
+
+    let mut s = String::new();
+    for line in lines {
+        fold(&mut s, &line);
+    }
+    s
+}
+
+/// 解析 iCalendar 文本中的 VTODO，其他组件被忽略，没有 SUMMARY 的 VTODO 被跳过
+///
+/// 得到的任务尚未分配编号；CATEGORIES 中内容里没有的标签追加到内容末尾
+pub fn import(text: &str) -> Result<Vec<Task>> {
+    let mut tasks = Vec::new();
+    // 当前所在的组件，如 VCALENDAR、VTODO、VALARM
+    let mut components: Vec<(String, usize)> = Vec::new();
+    let mut todo = Todo::default();
+    let mut found = false;
+
+    for (line_no, line) in unfold(text) {
+        let at = |message: String| Error::InvalidCalendar(t!("ical.at_line", line_no, message));
+        let (name, value) =
+            split_property(&line).ok_or_else(|| at(t!("ical.invalid_property").to_string()))?;
+        match name.as_str() {
+            "BEGIN" => {
+                let component = value.to_ascii_uppercase();
+                if components.is_empty() && component != "VCALENDAR" {
+                    return Err(at(t!("ical.not_calendar").to_string()));
+                }
+                if component == "VTODO" {
+                    todo = Todo::default();
+                }
+                found = true;
+                components.push((component, line_no));
+            }
+            "END" => {
+                let component = value.to_ascii_uppercase();
+                match components.pop() {
+                    Some((begin, _)) if begin == component => (),
+                    Some((begin, _)) => return Err(at(t!("ical.unclosed", begin))),
+                    None => return Err(at(t!("ical.not_calendar").to_string())),
+                }
+                if component == "VTODO" {
+                    if let Some(task) = std::mem::take(&mut todo).into_task() {
+                        tasks.push(task);
+                    }
+                }
+            }
+            _ if components.last().is_some_and(|(c, _)| c == "VTODO") => {
+                todo.set(&name, &value).map_err(at)?;
+            }
+            _ => (),
+        }
+    }
+    if let Some((component, line_no)) = components.pop() {
+        return Err(Error::InvalidCalendar(t!(
+            "ical.at_line",
+            line_no,
+            t!("ical.unclosed", component)
+        )));
+    }
+    if !found {
+        return Err(Error::InvalidCalendar(t!("ical.not_calendar").to_string()));
+    }
+    Ok(tasks)
+}
+
+/// 解析中的 VTODO 的各个属性
+#[derive(Default)]
+struct Todo {
+    uid: Option<String>,
+    summary: Option<String>,
+    created: Option<Date>,
+    due: Option<Date>,
+    completed: Option<Date>,
+    priority: Priority,
+    status: Option<State>,
+    categories: Vec<String>,
+}
+
+impl Todo {
+    /// 只取属性的值，DATE 和 DATE-TIME 都只取日期部分，因此忽略 VALUE、TZID 等参数
+    fn set(&mut self, name: &str, value: &str) -> std::result::Result<(), String> {
+        let date = || parse_date(value).ok_or_else(|| t!("ical.invalid_date", value));
+        match name {
+            "UID" => self.uid = Some(unescape(value)).filter(|uid| !uid.is_empty()),
+            "SUMMARY" => self.summary = Some(unescape(value)),
+            "CREATED" => self.created = Some(date()?),
+            "DUE" => self.due = Some(date()?),
+            "COMPLETED" => self.completed = Some(date()?),
+            "PRIORITY" => {
+                let priority = value
+                    .trim()
+                    .parse()
+                    .map_err(|_| t!("ical.invalid_priority", value))?;
+                self.priority = from_priority(priority);
+            }
+            "STATUS" => self.status = Some(from_status(value)),
+            "CATEGORIES" => self.categories.extend(split_list(value)),
+            _ => (),
+        }
+        Ok(())
+    }
+
+    fn into_task(self) -> Option<Task> {
+        // 任务只有一行，内容中的换行替换为空格
+        let mut content = self
+            .summary?
+            .split_whitespace()
+            .collect::<Vec<_>>()
+            .join(" ");
+        if content.is_empty() {
+            return None;
+        }
+        let mut tags = parser::parse_tags(&content, &parser::tag_regex());
+        for category in &self.categories {
+            let tag: String = category
+                .trim()
+                .chars()
+                .map(|c| if c.is_alphanumeric() { c } else { '_' })
+                .collect();
+            if !tag.is_empty() && !tags.contains(&tag) {
+                content.push_str(&format!(" #{}", tag));
+                tags.push(tag);
+            }
+        }
+
+        let mut task = Task::new(self.priority, content, self.due);
+        task.state = match self.status {
+            Some(state) => state,
+            None if self.completed.is_some() => State::Completed,
+            None => State::Pending,
+        };
+        if task.state == State::Completed {
+            task.completed_at = Some(self.completed.unwrap_or_else(date::today));
+        }
+        if let Some(created) = self.created {
+            task.created_at = Some(created);
+        }
+        task.uid = self.uid.map(|uid| storable(&uid));
+        Some(task)
+    }
+}
+
+/// 任务清单中的字段不能包含空白和括号，UID 中的这些字符替换为 `_`
+fn storable(uid: &str) -> String {
+    uid.chars()
+        .map(|c| {
+            if c.is_whitespace() || c == '(' || c == ')' {
+                '_'
+            } else {
+                c
+            }
+        })
+        .collect()
+}
+
+/// 优先级在日历中的 PRIORITY 值，导入时取值相同的已有任务保留原来的优先级
+pub fn to_priority(priority: Priority) -> u8 {
+    match priority {
+        Priority::NONE => 0,
+        Priority::A => 1,
+        Priority::B => 5,
+        _ => 9,
+    }
+}
+
+/// 0 表示未定义，1-4 为高、5 为中、6-9 为低优先级
+fn from_priority(priority: u8) -> Priority {
+    match priority {
+        0 => Priority::NONE,
+        1..=4 => Priority::A,
+        5 => Priority::B,
+        _ => Priority::C,
+    }
+}
+
+fn to_status(state: &State) -> &'static str {
+    match state {
+        State::Pending | State::Waiting => "NEEDS-ACTION",
+        State::InProgress => "IN-PROCESS",
+        State::Completed => "COMPLETED",
+        State::Removed => "CANCELLED",
+    }
+}
+
+/// 其他状态视为未开始
+fn from_status(status: &str) -> State {
+    match status.trim().to_ascii_uppercase().as_str() {
+        "IN-PROCESS" => State::InProgress,
+        "COMPLETED" => State::Completed,
+        "CANCELLED" => State::Removed,
+        _ => State::Pending,
+    }
+}
+
+/// `YYYYMMDD` 格式的日期
+fn basic_date(date: Date) -> String {
+    date.format("%Y%m%d").unwrap_or_default()
+}
+
+/// 接受 DATE 和 DATE-TIME 两种取值，只取日期部分
+fn parse_date(value: &str) -> Option<Date> {
+    let digits = value.trim().get(..8)?;
+    if !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    Date::parse_exact(&format!(
+        "{}-{}-{}",
+        &digits[..4],
+        &digits[4..6],
+        &digits[6..]
+    ))
+}
+
+/// 以 CRLF 结尾，超出长度的行在字符边界处折行，续行以空格开头
+fn fold(s: &mut String, line: &str) {
+    let mut width = 0;
+    for c in line.chars() {
+        if width + c.len_utf8() > LINE_LIMIT {
+            s.push_str("\r\n ");
+            width = 1;
+        }
+        s.push(c);
+        width += c.len_utf8();
+    }
+    s.push_str("\r\n");
+}
+
+/// 合并折行，返回每个内容行及其起始行号
+fn unfold(text: &str) -> Vec<(usize, String)> {
+    let mut lines: Vec<(usize, String)> = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim_end_matches('\r');
+        match (line.strip_prefix([' ', '\t']), lines.last_mut()) {
+            (Some(rest), Some((_, last))) => last.push_str(rest),
+            _ if line.trim().is_empty() => (),
+            _ => lines.push((i + 1, line.to_string())),
+        }
+    }
+    lines
+}
+
+/// 将内容行拆分为大写的属性名和取值，参数中引号内的冒号不作为分隔符
+fn split_property(line: &str) -> Option<(String, String)> {
+    let mut quoted = false;
+    let colon = line.char_indices().find_map(|(i, c)| match c {
+        '"' => {
+            quoted = !quoted;
+            None
+        }
+        ':' if !quoted => Some(i),
+        _ => None,
+    })?;
+    let (head, value) = (&line[..colon], &line[colon + 1..]);
+    let name = head.split(';').next().unwrap_or_default();
+    if name.is_empty() {
+        return None;
+    }
+    Some((name.to_ascii_uppercase(), value.to_string()))
+}
+
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace(';', "\\;")
+        .replace(',', "\\,")
+        .replace('\n', "\\n")
+}
+
+fn unescape(text: &str) -> String {
+    split_escaped(text, None).pop().unwrap_or_default()
+}
+
+/// 以未转义的逗号分隔的列表，如 CATEGORIES
+fn split_list(text: &str) -> Vec<String> {
+    split_escaped(text, Some(','))
+        .into_iter()
+        .filter(|item| !item.trim().is_empty())
+        .collect()
+}
+
+fn split_escaped(text: &str, separator: Option<char>) -> Vec<String> {
+    let mut items = vec![String::new()];
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        let item = items.last_mut().unwrap();
+        match c {
+            '\\' => match chars.next() {
+                Some('n' | 'N') => item.push('\n'),
+                Some(c) => item.push(c),
+                None => item.push('\\'),
+            },
+            c if Some(c) == separator => items.push(String::new()),
+            c => item.push(c),
+        }
+    }
+    items
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::LineParser;
+
+    fn task(line: &str) -> Task {
+        LineParser::new().parse_line(line).unwrap()
+    }
+
+    fn fmt(date: Option<Date>) -> Option<String> {
+        date.map(|date| date.fmt())
+    }
+
+    #[test]
+    fn round_trip() {
+        let tasks = vec![
+            task("[A] call mom, then; dad \\ #family (2024-01-01) (due:2024-02-01) (id:1)"),
+            task("▶ [B] write report (2024-01-02) (id:2)"),
+            task("✓ [C] ship it (2024-01-03) (id:3) (2024-01-05)"),
+            task("✗ [-] dropped (2024-01-04) (uid:abc@example.com) (id:4)"),
+            task("[-] 很长的内容会被折行，折行时不会拆开多字节的字符，导入后应与原来的内容完全一致 (2024-01-05) (id:5)"),
+        ];
+        let text = export(&tasks);
+        assert!(text.lines().all(|line| line.len() <= LINE_LIMIT));
+
+        let imported = import(&text).unwrap();
+        assert_eq!(imported.len(), tasks.len());
+        for (before, after) in tasks.iter().zip(&imported) {
+            assert_eq!(after.content, before.content);
+            assert_eq!(after.priority.as_str(), before.priority.as_str());
+            assert!(after.state == before.state, "{}", before.content);
+            assert_eq!(fmt(after.created_at), fmt(before.created_at));
+            assert_eq!(fmt(after.due_to), fmt(before.due_to));
+            assert_eq!(fmt(after.completed_at), fmt(before.completed_at));
+            assert_eq!(after.tags, before.tags);
+            assert_eq!(after.uid.as_deref(), Some(uid(before).as_str()));
+        }
+    }
+
+    #[test]
+    fn missing_creation_date_is_not_exported() {
+        let text = export([&task("[-] undated (-) (id:7)")]);
+        assert!(!text.contains("CREATED"));
+        assert!(text.contains("UID:7@tdt"));
+    }
+
+    #[test]
+    fn lossy_mappings() {
+        let tasks = [
+            task("⏸ [-] waiting (2024-01-01) (id:1)"),
+            task("[Z] lowest (2024-01-01) (id:2)"),
+        ];
+        let imported = import(&export(&tasks)).unwrap();
+        // 日历中没有等待中的状态，C 以下的优先级都导出为 9
+        assert!(imported[0].state == State::Pending);
+        assert_eq!(imported[1].priority.as_str(), "C");
+    }
+
+    #[test]
+    fn import_other_calendars() {
+        let text = "BEGIN:VCALENDAR\r\n\
+            BEGIN:VTODO\r\n\
+            UID:one two\r\n\
+            SUMMARY:buy\r\n  milk\r\n\
+            DUE;TZID=Europe/Paris:20240301T090000\r\n\
+            PRIORITY:5\r\n\
+            CATEGORIES:home,errand list\r\n\
+            BEGIN:VALARM\r\n\
+            SUMMARY:ignored\r\n\
+            END:VALARM\r\n\
+            END:VTODO\r\n\
+            BEGIN:VTODO\r\n\
+            UID:no-summary\r\n\
+            END:VTODO\r\n\
+            BEGIN:VEVENT\r\n\
+            SUMMARY:not a todo\r\n\
+            END:VEVENT\r\n\
+            END:VCALENDAR\r\n";
+        let tasks = import(text).unwrap();
+        assert_eq!(tasks.len(), 1);
+        let task = &tasks[0];
+        assert_eq!(task.content, "buy milk #home #errand_list");
+        assert_eq!(fmt(task.due_to).as_deref(), Some("2024-03-01"));
+        assert_eq!(task.priority.as_str(), "B");
+        assert_eq!(task.uid.as_deref(), Some("one_two"));
+    }
+
+    #[test]
+    fn invalid_calendars() {
+        for text in [
+            "",
+            "BEGIN:VTODO\nSUMMARY:x\nEND:VTODO\n",
+            "BEGIN:VCALENDAR\nBEGIN:VTODO\nSUMMARY:x\n",
+            "BEGIN:VCALENDAR\nBEGIN:VTODO\nEND:VCALENDAR\n",
+            "BEGIN:VCALENDAR\nBEGIN:VTODO\nDUE:tomorrow\nEND:VTODO\nEND:VCALENDAR\n",
+            "BEGIN:VCALENDAR\nBEGIN:VTODO\nPRIORITY:high\nEND:VTODO\nEND:VCALENDAR\n",
+            "BEGIN:VCALENDAR\nnot a property\nEND:VCALENDAR\n",
+        ] {
+            assert!(import(text).is_err(), "{:?}", text);
+        }
+    }
+}
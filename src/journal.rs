@@ -0,0 +1,512 @@
+use chrono::{Local, NaiveDateTime};
+use regex::Regex;
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use crate::error::{Error, Result};
+use crate::parser::LineParser;
+use crate::t;
+use crate::task::Task;
+
+const TIME_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+
+/// 日志中记录的操作
+#[derive(Clone, Copy, PartialEq)]
+pub enum Action {
+    Add,
+    Done,
+    Modify,
+    /// 标记为进行中
+    Start,
+    /// 标记为等待中
+    Wait,
+    /// 在编辑器中修改，可能同时包含新增、修改和删除
+    Edit,
+    Remove,
+    Delete,
+    /// 移入归档文件
+    Archive,
+    /// 从 iCalendar 文件导入，可能同时包含新增和修改
+    Import,
+    /// 修复无法解析的行，撤销时放回原文
+    Repair,
+    Undo(usize),
+    Redo(usize),
+}
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Add => f.write_str("add"),
+            Self::Done => f.write_str("done"),
+            Self::Modify => f.write_str("modify"),
+            Self::Start => f.write_str("start"),
+            Self::Wait => f.write_str("wait"),
+            Self::Edit => f.write_str("edit"),
+            Self::Remove => f.write_str("remove"),
+            Self::Delete => f.write_str("delete"),
+            Self::Archive => f.write_str("archive"),
+            Self::Import => f.write_str("import"),
+            Self::Repair => f.write_str("repair"),
+            Self::Undo(seq) => write!(f, "undo {}", seq),
+            Self::Redo(seq) => write!(f, "redo {}", seq),
+        }
+    }
+}
+
+impl FromStr for Action {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid = || Error::InvalidJournal(t!("journal.invalid_action", s));
+        let seq = |arg: Option<&str>| arg.and_then(|n| n.parse().ok()).ok_or_else(invalid);
+
+        let mut parts = s.split_whitespace();
+        match parts.next() {
+            Some("add") => Ok(Self::Add),
+            Some("done") => Ok(Self::Done),
+            Some("modify") => Ok(Self::Modify),
+            Some("start") => Ok(Self::Start),
+            Some("wait") => Ok(Self::Wait),
+            Some("edit") => Ok(Self::Edit),
+            Some("remove") => Ok(Self::Remove),
+            Some("delete") => Ok(Self::Delete),
+            Some("archive") => Ok(Self::Archive),
+            Some("import") => Ok(Self::Import),
+            Some("repair") => Ok(Self::Repair),
+            Some("undo") => Ok(Self::Undo(seq(parts.next())?)),
+            Some("redo") => Ok(Self::Redo(seq(parts.next())?)),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+/// 对单个任务的修改，before 为空表示新增，after 为空表示删除
+#[derive(Clone)]
+pub struct Change {
+    pub before: Option<Task>,
+    pub after: Option<Task>,
+    /// 删除时任务在清单中的位置，撤销删除时放回原处；旧的日志中没有记录时放在末尾
+    pub row: Option<usize>,
+    /// 只在同一次提交中对该编号的任务的修改已应用时才应用，不记入日志
+    pub requires: Option<usize>,
+    /// 修复前无法解析的原文，撤销修复时放回任务清单
+    pub line: Option<String>,
+}
+
+impl Change {
+    pub fn add(after: Task) -> Self {
+        Self {
+            before: None,
+            after: Some(after),
+            row: None,
+            requires: None,
+            line: None,
+        }
+    }
+
+    /// 新增任务，只在对编号为 `id` 的任务的修改应用时才应用
+    pub fn add_after(after: Task, id: usize) -> Self {
+        Self {
+            requires: Some(id),
+            ..Self::add(after)
+        }
+    }
+
+    pub fn update(before: &Task, after: Task) -> Self {
+        Self {
+            before: Some(before.clone()),
+            after: Some(after),
+            row: None,
+            requires: None,
+            line: None,
+        }
+    }
+
+    /// 将任务标记为今天完成；重复任务还会添加下一次任务，完成的修改因冲突被放弃时也不添加
+    pub fn complete(before: &Task) -> Vec<Self> {
+        let mut after = before.clone();
+        let next = after.complete();
+        let mut changes = vec![Self::update(before, after)];
+        changes.extend(next.map(|next| Self::add_after(next, before.id)));
+        changes
+    }
+
+    pub fn delete(before: &Task) -> Self {
+        Self {
+            before: Some(before.clone()),
+            after: None,
+            row: None,
+            requires: None,
+            line: None,
+        }
+    }
+
+    pub fn id(&self) -> usize {
+        self.before
+            .as_ref()
+            .or(self.after.as_ref())
+            .map_or(0, |task| task.id)
+    }
+
+    /// 撤销这一修改所需的修改
+    pub fn inverse(&self) -> Self {
+        Self {
+            before: self.after.clone(),
+            after: self.before.clone(),
+            row: self.row,
+            requires: None,
+            line: self.line.clone(),
+        }
+    }
+}
+
+/// 一条日志，记录一次操作及其对各个任务的修改
+#[derive(Clone)]
+pub struct Entry {
+    pub seq: usize,
+    pub time: NaiveDateTime,
+    pub action: Action,
+    pub changes: Vec<Change>,
+}
+
+/// 日志文件与任务清单位于同一目录，如 `todo.txt.journal`
+pub fn journal_path(file_path: &Path) -> PathBuf {
+    let mut name = file_path.file_name().unwrap_or_default().to_os_string();
+    name.push(".journal");
+    file_path.with_file_name(name)
+}
+
+/// 读取全部日志条目，日志文件不存在时返回空列表
+///
+/// 末尾没有换行符的行是被中断的写入留下的，无法解析时忽略
+pub fn read(file_path: &Path) -> Result<Vec<Entry>> {
+    let text = match fs::read_to_string(journal_path(file_path)) {
+        Ok(text) => text,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e.into()),
+    };
+
+    let parser = LineParser::new();
+    let mut entries: Vec<Entry> = Vec::new();
+    for line in text.split_inclusive('\n') {
+        match read_line(&parser, &mut entries, line.trim_end_matches(['\n', '\r'])) {
+            Err(_) if !line.ends_with('\n') => break,
+            result => result?,
+        }
+    }
+    Ok(entries)
+}
+
+fn read_line(parser: &LineParser, entries: &mut Vec<Entry>, line: &str) -> Result<()> {
+    if let Some(header) = line.strip_prefix('#') {
+        entries.push(parse_header(header)?);
+        return Ok(());
+    }
+    let entry = entries
+        .last_mut()
+        .ok_or_else(|| Error::InvalidJournal(t!("journal.missing_header").to_string()))?;
+    // 删除的任务记为 `-N 快照`，N 为它在清单中的位置
+    if let Some((row, before)) = line.strip_prefix('-').and_then(|rest| rest.split_once(' ')) {
+        let mut change = Change::delete(&parse_task(parser, before)?);
+        if !row.is_empty() {
+            let row = row
+                .parse()
+                .map_err(|_| Error::InvalidJournal(t!("journal.invalid_line", line)))?;
+            change.row = Some(row);
+        }
+        entry.changes.push(change);
+    } else if let Some(after) = line.strip_prefix("+ ") {
+        let after = parse_task(parser, after)?;
+        // 紧跟在同一任务修改前快照之后的是修改后快照
+        match entry.changes.last_mut() {
+            Some(change) if change.after.is_none() && change.id() == after.id => {
+                change.after = Some(after)
+            }
+            _ => entry.changes.push(Change::add(after)),
+        }
+    } else if let Some(text) = line.strip_prefix("! ") {
+        // 修复的任务之后是修复前的原文
+        match entry.changes.last_mut() {
+            Some(change) if change.after.is_some() => change.line = Some(text.to_string()),
+            _ => return Err(Error::InvalidJournal(t!("journal.invalid_line", line))),
+        }
+    } else if !line.is_empty() {
+        return Err(Error::InvalidJournal(t!("journal.invalid_line", line)));
+    }
+    Ok(())
+}
+
+fn parse_task(parser: &LineParser, line: &str) -> Result<Task> {
+    parser
+        .parse_line(line)
+        .map_err(|e| Error::InvalidJournal(format!("{}: {}", e.error, line)))
+}
+
+fn parse_header(header: &str) -> Result<Entry> {
+    let invalid = || Error::InvalidJournal(t!("journal.invalid_entry", header));
+
+    let (seq, rest) = header.split_once(' ').ok_or_else(invalid)?;
+    let time = rest.get(..19).ok_or_else(invalid)?;
+    Ok(Entry {
+        seq: seq.parse().map_err(|_| invalid())?,
+        time: NaiveDateTime::parse_from_str(time, TIME_FORMAT).map_err(|_| invalid())?,
+        action: rest[19..].trim().parse()?,
+        changes: Vec::new(),
+    })
+}
+
+/// 日志的快照中出现过的最大任务编号，日志文件不存在时为 0
+///
+/// 只匹配快照末尾的 `(id:N)` 字段，无法识别的行被跳过，因此日志损坏也不影响分配编号
+pub fn max_id(file_path: &Path) -> Result<usize> {
+    let file = match File::open(journal_path(file_path)) {
+        Ok(file) => file,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(0),
+        Err(e) => return Err(e.into()),
+    };
+
+    // 编号之后只可能有完成日期
+    let re_id = Regex::new(r"\(id:(\d+)\)(?: \(\d{4}-\d{2}-\d{2}\))?\s*$").unwrap();
+    let mut max_id = 0;
+    for line in BufReader::new(file).lines() {
+        let line = line?;
+        if !line.starts_with('-') && !line.starts_with("+ ") {
+            continue;
+        }
+        if let Some(id) = re_id
+            .captures(&line)
+            .and_then(|caps| caps[1].parse::<usize>().ok())
+        {
+            max_id = max_id.max(id);
+        }
+    }
+    Ok(max_id)
+}
+
+pub fn next_seq(entries: &[Entry]) -> usize {
+    entries.last().map_or(0, |entry| entry.seq) + 1
+}
+
+/// 不解析整个日志而得到下一条日志的序号，只读取各条目开头的序号，
+/// 因此日志中有无法解析的行时仍可在写入任务清单之前确定序号
+pub fn peek_next_seq(file_path: &Path) -> Result<usize> {
+    let text = match fs::read_to_string(journal_path(file_path)) {
+        Ok(text) => text,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(1),
+        Err(e) => return Err(e.into()),
+    };
+
+    let mut last_seq = 0;
+    // 不完整的行在下次追加时被截掉
+    for line in text
+        .split_inclusive('\n')
+        .filter(|line| line.ends_with('\n'))
+    {
+        let seq = line
+            .strip_prefix('#')
+            .and_then(|header| header.split_once(' '))
+            .and_then(|(seq, _)| seq.parse().ok());
+        if let Some(seq) = seq {
+            last_seq = seq;
+        }
+    }
+    Ok(last_seq + 1)
+}
+
+/// 追加一条日志，调用方需持有任务清单的锁
+pub fn append(file_path: &Path, seq: usize, action: Action, changes: &[Change]) -> Result<()> {
+    let mut s = format!("#{} {} {}\n", seq, Local::now().format(TIME_FORMAT), action);
+    for change in changes {
+        if let Some(before) = &change.before {
+            let row = match change.row.filter(|_| change.after.is_none()) {
+                Some(row) => row.to_string(),
+                None => String::new(),
+            };
+            s.push_str(&format!("-{} {}\n", row, before.stringify()));
+        }
+        if let Some(after) = &change.after {
+            s.push_str(&format!("+ {}\n", after.stringify()));
+        }
+        if let Some(line) = &change.line {
+            s.push_str(&format!("! {}\n", line));
+        }
+    }
+
+    let mut file = OpenOptions::new()
+        .read(true)
+        .append(true)
+        .create(true)
+        .open(journal_path(file_path))?;
+    // 截掉被中断的写入留下的不完整的行
+    let len = file.metadata()?.len();
+    if len > 0 {
+        let mut contents = Vec::new();
+        file.read_to_end(&mut contents)?;
+        if contents.last() != Some(&b'\n') {
+            let end = contents
+                .iter()
+                .rposition(|b| *b == b'\n')
+                .map_or(0, |i| i + 1);
+            file.set_len(end as u64)?;
+        }
+    }
+    file.write_all(s.as_bytes())?;
+    file.sync_data()?;
+    Ok(())
+}
+
+/// 回放日志，返回 (可撤销的条目序号, 可重做的条目序号)，最近的在末尾
+pub fn stacks(entries: &[Entry]) -> (Vec<usize>, Vec<usize>) {
+    let mut undo_stack = Vec::new();
+    let mut redo_stack = Vec::new();
+    for entry in entries {
+        match entry.action {
+            Action::Undo(seq) => {
+                undo_stack.retain(|s| *s != seq);
+                redo_stack.push(seq);
+            }
+            Action::Redo(seq) => {
+                redo_stack.retain(|s| *s != seq);
+                undo_stack.push(seq);
+            }
+            _ => {
+                undo_stack.push(entry.seq);
+                redo_stack.clear();
+            }
+        }
+    }
+    (undo_stack, redo_stack)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::priority::Priority;
+    use std::env;
+    use std::process;
+
+    fn temp_list(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("tdt-journal-{}-{}", process::id(), name));
+        _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir.join("todo.txt")
+    }
+
+    fn entry(seq: usize, action: Action) -> Entry {
+        Entry {
+            seq,
+            time: NaiveDateTime::default(),
+            action,
+            changes: Vec::new(),
+        }
+    }
+
+    fn task(id: usize, content: &str) -> Task {
+        let mut task = Task::new(Priority::NONE, content.to_string(), None);
+        task.id = id;
+        task
+    }
+
+    fn describe(change: &Change) -> String {
+        let stringify = |task: &Option<Task>| task.as_ref().map(Task::stringify);
+        format!(
+            "{:?} {:?} {:?}",
+            stringify(&change.before),
+            stringify(&change.after),
+            change.row
+        )
+    }
+
+    #[test]
+    fn stacks_follow_undo_and_redo() {
+        let mut entries = vec![
+            entry(1, Action::Add),
+            entry(2, Action::Archive),
+            entry(3, Action::Undo(2)),
+        ];
+        // 撤销归档后它可以重做
+        assert_eq!(stacks(&entries), (vec![1], vec![2]));
+
+        entries.push(entry(4, Action::Redo(2)));
+        assert_eq!(stacks(&entries), (vec![1, 2], vec![]));
+
+        entries.push(entry(5, Action::Undo(2)));
+        entries.push(entry(6, Action::Undo(1)));
+        assert_eq!(stacks(&entries), (vec![], vec![2, 1]));
+
+        // 新的操作清空重做栈
+        entries.push(entry(7, Action::Done));
+        assert_eq!(stacks(&entries), (vec![7], vec![]));
+    }
+
+    #[test]
+    fn append_then_read_round_trip() {
+        let path = temp_list("round-trip");
+        let mut deleted = Change::delete(&task(2, "bravo"));
+        deleted.row = Some(1);
+        let changes = vec![
+            Change::add(task(1, "alpha")),
+            Change::update(&task(3, "charlie"), task(3, "charlie edited")),
+            deleted,
+        ];
+        append(&path, 1, Action::Edit, &changes).unwrap();
+
+        let entries = read(&path).unwrap();
+        assert_eq!(entries.len(), 1);
+        assert!(entries[0].action == Action::Edit);
+        let read_back: Vec<String> = entries[0].changes.iter().map(describe).collect();
+        let expected: Vec<String> = changes.iter().map(describe).collect();
+        assert_eq!(read_back, expected);
+        assert_eq!(max_id(&path).unwrap(), 3);
+    }
+
+    #[test]
+    fn truncated_last_line_is_ignored_and_replaced() {
+        let path = temp_list("truncated");
+        append(&path, 1, Action::Add, &[Change::add(task(1, "alpha"))]).unwrap();
+        // 被中断的写入留下不完整的行
+        let mut file = OpenOptions::new()
+            .append(true)
+            .open(journal_path(&path))
+            .unwrap();
+        file.write_all(b"#2 2024-01-01 00:0").unwrap();
+        drop(file);
+
+        let entries = read(&path).unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(peek_next_seq(&path).unwrap(), 2);
+
+        append(&path, 2, Action::Add, &[Change::add(task(2, "bravo"))]).unwrap();
+        let entries = read(&path).unwrap();
+        assert_eq!(
+            entries.iter().map(|e| e.seq).collect::<Vec<_>>(),
+            vec![1, 2]
+        );
+        let text = fs::read_to_string(journal_path(&path)).unwrap();
+        assert!(!text.contains("00:0#"));
+    }
+
+    #[test]
+    fn deletion_keeps_its_row() {
+        let path = temp_list("row");
+        let mut change = Change::delete(&task(5, "echo"));
+        change.row = Some(3);
+        append(&path, 1, Action::Delete, &[change]).unwrap();
+        let text = fs::read_to_string(journal_path(&path)).unwrap();
+        assert!(text
+            .lines()
+            .nth(1)
+            .is_some_and(|line| line.starts_with("-3 ")));
+
+        let change = &read(&path).unwrap()[0].changes[0];
+        assert_eq!(change.row, Some(3));
+        // 撤销时在同一位置重新插入
+        let inverse = change.inverse();
+        assert_eq!(inverse.row, Some(3));
+        assert!(inverse.before.is_none() && inverse.after.is_some());
+    }
+}
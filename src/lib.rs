@@ -0,0 +1,49 @@
+//! 一个基于纯文本的 to-do 清单，受到 todo.txt 的启发
+//!
+//! [`TodoList`] 负责读取、查询、修改和保存任务清单，`tdt` 命令行工具是它的前端：
+//!
+//! ```no_run
+//! use todotxt::{Action, Change, Query, TodoList};
+//!
+//! let mut list = TodoList::open("todo.txt")?;
+//! let query = Query::parse("+work and pri>=B")?;
+//! let mut changes = Vec::new();
+//! for task in list.query(&query) {
+//!     let mut done = task.clone();
+//!     // 重复任务完成后生成下一次任务
+//!     if let Some(next) = done.complete() {
+//!         changes.push(Change::add(next));
+//!     }
+//!     changes.push(Change::update(task, done));
+//! }
+//! list.commit(Action::Done, changes)?;
+//! # Ok::<(), todotxt::Error>(())
+//! ```
+
+pub mod codec;
+pub mod date;
+pub mod deps;
+pub mod error;
+pub mod i18n;
+pub mod ical;
+pub mod journal;
+mod list;
+pub mod parser;
+pub mod priority;
+pub mod query;
+pub mod recurrence;
+pub mod state;
+pub mod storage;
+mod task;
+
+pub use codec::Codec;
+pub use date::Date;
+pub use error::{Error, Result};
+pub use journal::{Action, Change, Entry};
+pub use list::{Commit, Conflict, TodoList};
+pub use parser::ParseMode;
+pub use priority::Priority;
+pub use query::Query;
+pub use recurrence::Recurrence;
+pub use state::State;
+pub use task::Task;
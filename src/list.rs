@@ -0,0 +1,811 @@
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+use crate::codec::Codec;
+use crate::deps;
+use crate::error::{Error, Result};
+use crate::journal::{self, Action, Change, Entry};
+use crate::parser::{self, Document, InvalidLine, ParseMode, Repairer};
+use crate::query::Query;
+use crate::storage;
+use crate::task::Task;
+
+/// 默认的归档文件名，位于任务清单所在的目录
+const ARCHIVE_FILE: &str = "done.txt";
+
+/// 修改无法应用的原因
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Conflict {
+    /// 任务的当前内容与修改前快照不一致
+    Modified,
+    /// 要修改的任务已不存在
+    Deleted,
+    /// 要新增的任务编号已被占用
+    Exists,
+}
+
+/// 一次提交的结果
+#[derive(Default)]
+pub struct Commit {
+    /// 已应用并记入日志的修改，新任务的编号已分配
+    pub applied: Vec<Change>,
+    /// 因任务已被其他进程修改或删除而放弃的修改
+    pub conflicts: Vec<(usize, Conflict)>,
+}
+
+/// 任务清单文件及其内容
+///
+/// 读取后的内容保存在内存中；[`TodoList::commit`] 等写入操作会在锁内重新读取文件，
+/// 按编号应用修改后原子地写回，并记入操作日志，从而不会覆盖其他进程在此期间的写入
+pub struct TodoList {
+    path: PathBuf,
+    archive: PathBuf,
+    backup: bool,
+    codec: Codec,
+    mode: ParseMode,
+    doc: Document,
+}
+
+impl TodoList {
+    /// 打开任务清单，格式根据文件内容推断，无法解析的行原样保留
+    pub fn open(path: impl Into<PathBuf>) -> Result<Self> {
+        Self::open_with(path, None, ParseMode::Tolerant)
+    }
+
+    /// 以指定的格式和解析方式打开任务清单，文件不存在时得到空清单
+    pub fn open_with(
+        path: impl Into<PathBuf>,
+        codec: Option<Codec>,
+        mode: ParseMode,
+    ) -> Result<Self> {
+        let path = path.into();
+        let codec = codec.unwrap_or_else(|| Codec::detect(&path));
+        let mut list = Self {
+            archive: path.with_file_name(ARCHIVE_FILE),
+            backup: false,
+            path,
+            codec,
+            mode,
+            doc: Document::default(),
+        };
+        list.reload()?;
+        Ok(list)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn codec(&self) -> Codec {
+        self.codec
+    }
+
+    /// 归档文件，默认为任务清单所在目录下的 `done.txt`
+    pub fn archive_path(&self) -> &Path {
+        &self.archive
+    }
+
+    pub fn set_archive_path(&mut self, path: impl Into<PathBuf>) {
+        self.archive = path.into();
+    }
+
+    /// 每次写入前是否将上一版本保留为 `.bak` 文件，默认不保留
+    pub fn set_backup(&mut self, backup: bool) {
+        self.backup = backup;
+    }
+
+    /// 按文件中的顺序排列的任务
+    pub fn tasks(&self) -> &[Task] {
+        &self.doc.tasks
+    }
+
+    /// 直接修改内存中的任务，需调用 [`TodoList::save`] 写回
+    pub fn tasks_mut(&mut self) -> &mut Vec<Task> {
+        &mut self.doc.tasks
+    }
+
+    /// 无法解析而原样保留的行
+    pub fn invalid_lines(&self) -> &[InvalidLine] {
+        &self.doc.invalid
+    }
+
+    pub fn get(&self, id: usize) -> Option<&Task> {
+        self.doc.tasks.iter().find(|task| task.id == id)
+    }
+
+    /// 按文件中的顺序返回匹配查询的任务
+    pub fn query<'a>(&'a self, query: &'a Query) -> impl Iterator<Item = &'a Task> + 'a {
+        self.doc
+            .tasks
+            .iter()
+            .filter(move |task| query.matches(task))
+    }
+
+    /// 重新读取任务清单
+    pub fn reload(&mut self) -> Result<()> {
+        self.doc = self.read()?;
+        Ok(())
+    }
+
+    /// 将内存中的任务整体写回，覆盖文件的当前内容且不记入日志
+    pub fn save(&mut self) -> Result<()> {
+        let _lock = storage::lock(&self.path)?;
+        assign_ids(&self.path, &mut self.doc.tasks)?;
+        self.write()
+    }
+
+    /// 添加一个任务，返回分配的编号
+    pub fn add(&mut self, task: Task) -> Result<usize> {
+        let commit = self.commit(Action::Add, vec![Change::add(task)])?;
+        Ok(commit.applied.first().map_or(0, Change::id))
+    }
+
+    /// 在锁内重新读取任务清单并按编号应用修改，从而保留其他进程在此期间的写入；
+    /// 若某个任务在此期间已被其他进程修改或删除，则放弃对它的修改。
+    /// 编号为 0 的新任务在此时分配编号，已删除或归档的任务的编号不会再分配；
+    /// 依赖其他修改的修改（见 [`Change::add_after`]）在其他修改之后应用，所依赖的修改被放弃时一并放弃
+    pub fn commit(&mut self, action: Action, changes: Vec<Change>) -> Result<Commit> {
+        let mut commit = Commit::default();
+        if changes.is_empty() {
+            return Ok(commit);
+        }
+
+        let _lock = storage::lock(&self.path)?;
+        self.doc = self.read()?;
+
+        let mut next_id = next_id(&self.path, &self.doc.tasks)?;
+        let (dependent, independent): (Vec<Change>, Vec<Change>) = changes
+            .into_iter()
+            .partition(|change| change.requires.is_some());
+        for mut change in independent.into_iter().chain(dependent) {
+            let required = change.requires.is_none_or(|id| {
+                commit
+                    .applied
+                    .iter()
+                    .any(|applied| applied.requires.is_none() && applied.id() == id)
+            });
+            if !required {
+                continue;
+            }
+            if let Some(task) = change.after.as_mut().filter(|task| task.id == 0) {
+                task.id = next_id;
+                next_id += 1;
+            }
+            match apply_change(&mut self.doc.tasks, &mut change) {
+                Ok(()) => commit.applied.push(change),
+                Err(conflict) => commit.conflicts.push((change.id(), conflict)),
+            }
+        }
+        if commit.applied.is_empty() {
+            return Ok(commit);
+        }
+        // 新的环必然经过依赖关系改变了的任务，清单中原有的环不妨碍其他修改
+        let cycle = commit
+            .applied
+            .iter()
+            .filter_map(|change| change.after.as_ref().map(|after| (change, after)))
+            .filter(|(change, after)| {
+                !after.deps.is_empty()
+                    && change
+                        .before
+                        .as_ref()
+                        .is_none_or(|before| before.deps != after.deps)
+            })
+            .find_map(|(_, after)| deps::find_cycle(&self.doc.tasks, after.id));
+        if let Some(cycle) = cycle {
+            self.doc = self.read()?;
+            return Err(Error::DependencyCycle(cycle));
+        }
+
+        // 序号在写入任务清单之前确定，日志中有无法解析的行也不会使已写入的修改报错
+        let seq = journal::peek_next_seq(&self.path)?;
+        self.write()?;
+        journal::append(&self.path, seq, action, &commit.applied)?;
+        Ok(commit)
+    }
+
+    /// 将匹配的任务移入归档文件，返回被归档的任务
+    ///
+    /// 归档的任务不再占用编号；在日志中记为一次 `archive` 操作，撤销时移回任务清单
+    pub fn archive(&mut self, filter: impl Fn(&Task) -> bool) -> Result<Vec<Task>> {
+        let _lock = storage::lock(&self.path)?;
+        self.doc = self.read()?;
+        let mut changes: Vec<Change> = Vec::new();
+        for (row, task) in self.doc.tasks.iter().enumerate() {
+            if filter(task) {
+                let mut change = Change::delete(task);
+                // 与逐个删除时的位置一致，撤销时倒序放回即恢复原来的顺序
+                change.row = Some(row - changes.len());
+                changes.push(change);
+            }
+        }
+        if changes.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        // 先写入归档再从任务清单中删除，中途失败时任务不会丢失
+        let archived: Vec<Task> = changes.iter().filter_map(|c| c.before.clone()).collect();
+        let seq = journal::peek_next_seq(&self.path)?;
+        self.append_archive(&archived)?;
+        self.doc.tasks.retain(|task| !filter(task));
+        self.write()?;
+        journal::append(&self.path, seq, Action::Archive, &changes)?;
+        Ok(archived)
+    }
+
+    /// 归档文件中的任务，按文件中的顺序排列，没有编号
+    pub fn archived(&self) -> Result<Vec<Task>> {
+        if !self.archive.exists() {
+            return Ok(Vec::new());
+        }
+        let doc = parser::parse_file(&self.archive, self.codec, ParseMode::Tolerant)?;
+        Ok(doc
+            .tasks
+            .into_iter()
+            .map(|mut task| {
+                task.id = 0;
+                task.line = 0;
+                task
+            })
+            .collect())
+    }
+
+    /// 尝试修复无法解析的行，返回修复前的行及修复后的任务
+    ///
+    /// 在日志中记为一次 `repair` 操作，撤销时放回修复前的原文
+    pub fn repair(&mut self) -> Result<Vec<(InvalidLine, Task)>> {
+        let _lock = storage::lock(&self.path)?;
+        self.doc = self.read_with(ParseMode::Tolerant)?;
+        if self.doc.invalid.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        // 从后往前修复，使插入的任务不影响前面的行的位置
+        let repairer = Repairer::new(self.codec);
+        let mut repaired = Vec::new();
+        for i in (0..self.doc.invalid.len()).rev() {
+            let Some(task) = repairer.repair_line(&self.doc.invalid[i].text) else {
+                continue;
+            };
+            let line = self.doc.invalid.remove(i);
+            let position = line.position.min(self.doc.tasks.len());
+            self.doc.tasks.insert(position, task);
+            for later in &mut self.doc.invalid[i..] {
+                later.position += 1;
+            }
+            repaired.push(line);
+        }
+        if repaired.is_empty() {
+            return Err(Error::Unrepairable);
+        }
+
+        assign_ids(&self.path, &mut self.doc.tasks)?;
+        repaired.reverse();
+        // 前面每修复一行，后面的任务就后移一位
+        let repaired: Vec<(InvalidLine, Task)> = repaired
+            .into_iter()
+            .enumerate()
+            .map(|(k, line)| {
+                let row = (line.position + k).min(self.doc.tasks.len() - 1);
+                (line, self.doc.tasks[row].clone())
+            })
+            .collect();
+        let changes: Vec<Change> = repaired
+            .iter()
+            .map(|(line, task)| Change {
+                line: Some(line.text.clone()),
+                ..Change::add(task.clone())
+            })
+            .collect();
+
+        let seq = journal::peek_next_seq(&self.path)?;
+        self.write()?;
+        journal::append(&self.path, seq, Action::Repair, &changes)?;
+        Ok(repaired)
+    }
+
+    /// 全部操作日志，最早的在前
+    pub fn history(&self) -> Result<Vec<Entry>> {
+        journal::read(&self.path)
+    }
+
+    /// 撤销最近的若干条操作，返回被撤销的条目
+    pub fn undo(&mut self, count: usize) -> Result<Vec<Entry>> {
+        self.replay(count, true)
+    }
+
+    /// 重做最近被撤销的若干条操作，返回被重做的条目
+    pub fn redo(&mut self, count: usize) -> Result<Vec<Entry>> {
+        self.replay(count, false)
+    }
+
+    /// 撤销或重做最近的若干条操作，任何一条无法应用时都不做修改
+    fn replay(&mut self, count: usize, undo: bool) -> Result<Vec<Entry>> {
+        let _lock = storage::lock(&self.path)?;
+        let mut doc = self.read()?;
+        let entries = journal::read(&self.path)?;
+
+        let (undo_stack, redo_stack) = journal::stacks(&entries);
+        let stack = if undo { undo_stack } else { redo_stack };
+        if stack.is_empty() {
+            return Err(if undo {
+                Error::NothingToUndo
+            } else {
+                Error::NothingToRedo
+            });
+        }
+
+        let mut replayed = Vec::new();
+        // 撤销归档时从归档文件中移除，重做时重新写入
+        let mut unarchived = Vec::new();
+        let mut rearchived = Vec::new();
+        for seq in stack.iter().rev().take(count) {
+            let entry = entries.iter().find(|entry| entry.seq == *seq).unwrap();
+            let mut changes: Vec<Change> = if undo {
+                entry.changes.iter().rev().map(Change::inverse).collect()
+            } else {
+                entry.changes.clone()
+            };
+            for change in &mut changes {
+                let id = change.id();
+                let conflict = || Error::JournalConflict {
+                    id,
+                    seq: *seq,
+                    undo,
+                };
+                // 重做修复时重新取出原文，任务放在原文所在的位置
+                if let Some(text) = change.line.as_ref().filter(|_| !undo) {
+                    let i = doc
+                        .invalid
+                        .iter()
+                        .position(|line| &line.text == text)
+                        .ok_or_else(conflict)?;
+                    change.row = Some(doc.invalid.remove(i).position);
+                    for later in &mut doc.invalid[i..] {
+                        later.position += 1;
+                    }
+                }
+                if apply_change(&mut doc.tasks, change).is_err() {
+                    return Err(conflict());
+                }
+                // 撤销修复时在任务原来的位置放回原文
+                if let (Some(text), Some(row), true) = (&change.line, change.row, undo) {
+                    self.restore_line(&mut doc, row, text);
+                }
+            }
+            if entry.action == Action::Archive {
+                let tasks = entry.changes.iter().filter_map(|c| c.before.clone());
+                if undo {
+                    unarchived.extend(tasks);
+                } else {
+                    rearchived.extend(tasks);
+                }
+            }
+            replayed.push(entry.clone());
+        }
+
+        self.append_archive(&rearchived)?;
+        self.doc = doc;
+        self.write()?;
+        self.remove_archived(&unarchived)?;
+        for (seq, entry) in (journal::next_seq(&entries)..).zip(&replayed) {
+            let action = if undo {
+                Action::Undo(entry.seq)
+            } else {
+                Action::Redo(entry.seq)
+            };
+            journal::append(&self.path, seq, action, &[])?;
+        }
+        Ok(replayed)
+    }
+
+    /// 将无法解析的原文放回第 `row` 个任务原来的位置，该任务已被删除
+    fn restore_line(&self, doc: &mut Document, row: usize, text: &str) {
+        let (column, reason) = match parser::parse_line_with(self.codec, text) {
+            Ok(_) => (1, String::new()),
+            Err(e) => (e.column, e.error.to_string()),
+        };
+        let i = doc.invalid.partition_point(|line| line.position <= row);
+        doc.invalid.insert(
+            i,
+            InvalidLine {
+                line_no: 0,
+                column,
+                position: row,
+                text: text.to_string(),
+                reason,
+            },
+        );
+        for later in &mut doc.invalid[i + 1..] {
+            later.position -= 1;
+        }
+    }
+
+    /// 归档文件中任务的文本，不带编号
+    fn archive_line(&self, task: &Task) -> String {
+        let mut task = task.clone();
+        task.id = 0;
+        self.codec.stringify(&task)
+    }
+
+    /// 在归档文件末尾追加任务，调用方需持有任务清单的锁
+    fn append_archive(&self, tasks: &[Task]) -> Result<()> {
+        if tasks.is_empty() {
+            return Ok(());
+        }
+        let mut contents = self.read_archive()?;
+        if !contents.is_empty() && !contents.ends_with('\n') {
+            contents.push('\n');
+        }
+        for task in tasks {
+            contents.push_str(&self.archive_line(task));
+            contents.push('\n');
+        }
+        storage::write_atomic(&self.archive, contents.as_bytes(), self.backup)
+    }
+
+    /// 从归档文件中移除任务，每个任务只移除最后一个相同的行
+    fn remove_archived(&self, tasks: &[Task]) -> Result<()> {
+        if tasks.is_empty() {
+            return Ok(());
+        }
+        let contents = self.read_archive()?;
+        let mut lines: Vec<&str> = contents.lines().collect();
+        for task in tasks {
+            let text = self.archive_line(task);
+            if let Some(i) = lines.iter().rposition(|line| *line == text) {
+                lines.remove(i);
+            }
+        }
+        let mut contents = lines.join("\n");
+        if !contents.is_empty() {
+            contents.push('\n');
+        }
+        storage::write_atomic(&self.archive, contents.as_bytes(), self.backup)
+    }
+
+    fn read_archive(&self) -> Result<String> {
+        match fs::read_to_string(&self.archive) {
+            Ok(contents) => Ok(contents),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(String::new()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn read(&self) -> Result<Document> {
+        self.read_with(self.mode)
+    }
+
+    fn read_with(&self, mode: ParseMode) -> Result<Document> {
+        if !self.path.exists() {
+            return Ok(Document::default());
+        }
+        let mut doc = parser::parse_file(&self.path, self.codec, mode)?;
+        assign_ids(&self.path, &mut doc.tasks)?;
+        Ok(doc)
+    }
+
+    /// 写回任务清单，无法解析的行按原来的位置原样写回，并更新各行的行号
+    fn write(&mut self) -> Result<()> {
+        let mut contents = String::new();
+        let mut line_no = 0;
+        let mut invalid = self.doc.invalid.iter_mut().peekable();
+        for (i, task) in self.doc.tasks.iter_mut().enumerate() {
+            while let Some(line) = invalid.next_if(|line| line.position <= i) {
+                contents.push_str(&line.text);
+                contents.push('\n');
+                line_no += 1;
+                line.line_no = line_no;
+            }
+            contents.push_str(&self.codec.stringify(task));
+            contents.push('\n');
+            line_no += 1;
+            task.line = line_no;
+        }
+        for line in invalid {
+            contents.push_str(&line.text);
+            contents.push('\n');
+            line_no += 1;
+            line.line_no = line_no;
+        }
+
+        storage::write_atomic(&self.path, contents.as_bytes(), self.backup)
+    }
+}
+
+/// 下一个可分配的编号，大于清单和日志中出现过的所有编号，
+/// 因此已删除或归档的任务的编号不会再分配给新任务
+fn next_id(path: &Path, tasks: &[Task]) -> Result<usize> {
+    let max_id = tasks.iter().map(|task| task.id).max().unwrap_or(0);
+    Ok(max_id.max(journal::max_id(path)?) + 1)
+}
+
+/// 为没有编号或编号重复的任务分配编号，只在需要时读取日志
+fn assign_ids(path: &Path, tasks: &mut [Task]) -> Result<()> {
+    if parser::missing_ids(tasks) {
+        parser::assign_ids(tasks, next_id(path, tasks)?);
+    }
+    Ok(())
+}
+
+/// 按编号应用修改，要求任务的当前内容与修改前快照一致
+///
+/// 删除任务时记下它所在的位置；新增的任务有记录的位置时插入该位置，否则放在末尾
+fn apply_change(tasks: &mut Vec<Task>, change: &mut Change) -> std::result::Result<(), Conflict> {
+    let row = tasks.iter().position(|task| task.id == change.id());
+    match (&change.before, row) {
+        (Some(before), Some(row)) if tasks[row].stringify() == before.stringify() => {
+            match &change.after {
+                Some(after) => tasks[row] = after.clone(),
+                None => {
+                    tasks.remove(row);
+                    change.row = Some(row);
+                }
+            }
+        }
+        (Some(_), Some(_)) => return Err(Conflict::Modified),
+        (Some(_), None) => return Err(Conflict::Deleted),
+        (None, Some(_)) => return Err(Conflict::Exists),
+        (None, None) => match (change.after.clone(), change.row) {
+            (Some(after), Some(row)) => tasks.insert(row.min(tasks.len()), after),
+            (after, _) => tasks.extend(after),
+        },
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::date::Date;
+    use crate::priority::Priority;
+    use crate::state::State;
+    use std::env;
+    use std::process;
+
+    /// 每个测试使用独立的临时目录，返回其中的任务清单路径
+    fn temp_list(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("tdt-test-{}-{}", process::id(), name));
+        _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir.join("todo.txt")
+    }
+
+    fn contents(list: &TodoList) -> Vec<String> {
+        list.tasks()
+            .iter()
+            .map(|task| task.content.clone())
+            .collect()
+    }
+
+    fn recurring(list: &mut TodoList) -> usize {
+        let mut task = Task::new(
+            Priority::NONE,
+            "delta".to_string(),
+            Date::parse_exact("2024-01-08"),
+        );
+        task.recurrence = Some("+1w".parse().unwrap());
+        list.add(task).unwrap()
+    }
+
+    #[test]
+    fn completing_a_recurring_task_adds_the_next_one() {
+        let path = temp_list("recurrence");
+        let mut list = TodoList::open(&path).unwrap();
+        let id = recurring(&mut list);
+
+        let changes = Change::complete(list.get(id).unwrap());
+        let commit = list.commit(Action::Done, changes).unwrap();
+        assert!(commit.conflicts.is_empty());
+        assert_eq!(commit.applied.len(), 2);
+        assert_eq!(list.tasks().len(), 2);
+        let next = &list.tasks()[1];
+        assert!(next.state == State::Pending);
+        assert_eq!(
+            next.due_to.map(|date| date.fmt()).as_deref(),
+            Some("2024-01-15")
+        );
+    }
+
+    #[test]
+    fn recurrence_is_dropped_when_completion_conflicts() {
+        let path = temp_list("recurrence-conflict");
+        let mut a = TodoList::open(&path).unwrap();
+        let id = recurring(&mut a);
+
+        // 另一个进程在此期间修改了这个任务
+        let mut b = TodoList::open(&path).unwrap();
+        let mut edited = b.get(id).unwrap().clone();
+        edited.set_content("delta edited").unwrap();
+        let change = Change::update(b.get(id).unwrap(), edited);
+        b.commit(Action::Modify, vec![change]).unwrap();
+
+        let changes = Change::complete(a.get(id).unwrap());
+        let commit = a.commit(Action::Done, changes).unwrap();
+        assert!(commit.applied.is_empty());
+        assert_eq!(commit.conflicts, vec![(id, Conflict::Modified)]);
+        assert_eq!(contents(&a), vec!["delta edited"]);
+        assert!(a.tasks()[0].state == State::Pending);
+        assert_eq!(journal::read(&path).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn concurrent_adds_are_merged() {
+        let path = temp_list("merge");
+        let mut a = TodoList::open(&path).unwrap();
+        let mut b = TodoList::open(&path).unwrap();
+        let alpha = a
+            .add(Task::new(Priority::NONE, "alpha".to_string(), None))
+            .unwrap();
+        // b 读取的内容已过时，提交时仍保留 a 写入的任务
+        let bravo = b
+            .add(Task::new(Priority::NONE, "bravo".to_string(), None))
+            .unwrap();
+        assert_ne!(alpha, bravo);
+        assert_eq!(contents(&b), vec!["alpha", "bravo"]);
+        a.reload().unwrap();
+        assert_eq!(contents(&a), vec!["alpha", "bravo"]);
+    }
+
+    #[test]
+    fn stale_changes_are_conflicts() {
+        let path = temp_list("conflict");
+        let mut a = TodoList::open(&path).unwrap();
+        let id = a
+            .add(Task::new(Priority::NONE, "alpha".to_string(), None))
+            .unwrap();
+        let mut b = TodoList::open(&path).unwrap();
+        let task = b.get(id).unwrap().clone();
+
+        // a 删除任务后，b 对它的修改放弃
+        a.commit(Action::Delete, vec![Change::delete(&task)])
+            .unwrap();
+        let mut edited = task.clone();
+        edited.set_content("alpha edited").unwrap();
+        let commit = b
+            .commit(Action::Modify, vec![Change::update(&task, edited)])
+            .unwrap();
+        assert_eq!(commit.conflicts, vec![(id, Conflict::Deleted)]);
+        assert!(b.tasks().is_empty());
+
+        // 新增的任务编号已被占用
+        let id = b
+            .add(Task::new(Priority::NONE, "bravo".to_string(), None))
+            .unwrap();
+        let mut duplicate = Task::new(Priority::NONE, "charlie".to_string(), None);
+        duplicate.id = id;
+        let commit = a.commit(Action::Add, vec![Change::add(duplicate)]).unwrap();
+        assert_eq!(commit.conflicts, vec![(id, Conflict::Exists)]);
+        assert_eq!(contents(&a), vec!["bravo"]);
+        assert_eq!(journal::read(&path).unwrap().len(), 3);
+    }
+
+    #[test]
+    fn commit_waits_for_lock() {
+        let path = temp_list("lock");
+        let mut list = TodoList::open(&path).unwrap();
+        let lock = storage::lock(&path).unwrap();
+        let task = Task::new(Priority::NONE, "alpha".to_string(), None);
+        assert!(matches!(list.add(task.clone()), Err(Error::Locked)));
+        assert!(!path.exists());
+
+        drop(lock);
+        list.add(task).unwrap();
+        assert_eq!(contents(&list), vec!["alpha"]);
+    }
+
+    #[test]
+    fn write_replaces_file_atomically() {
+        let path = temp_list("atomic");
+        fs::write(&path, "[A] alpha (2024-01-01) id:1\n").unwrap();
+        let mut list = TodoList::open(&path).unwrap();
+        list.add(Task::new(Priority::NONE, "bravo".to_string(), None))
+            .unwrap();
+
+        // 写入经由临时文件重命名完成，不留下临时文件
+        let dir = path.parent().unwrap();
+        let leftovers: Vec<_> = fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .filter(|name| name.ends_with(".tmp"))
+            .collect();
+        assert!(leftovers.is_empty(), "{:?}", leftovers);
+        let text = fs::read_to_string(&path).unwrap();
+        assert!(text.starts_with("[A] alpha (2024-01-01) id:1\n"));
+        assert!(text
+            .lines()
+            .nth(1)
+            .is_some_and(|line| line.starts_with("[-] bravo")));
+    }
+
+    #[test]
+    fn undo_restores_deleted_task_in_place() {
+        let path = temp_list("undo-row");
+        let mut list = TodoList::open(&path).unwrap();
+        for content in ["alpha", "bravo", "charlie"] {
+            list.add(Task::new(Priority::NONE, content.to_string(), None))
+                .unwrap();
+        }
+        let bravo = list.tasks()[1].clone();
+        list.commit(Action::Delete, vec![Change::delete(&bravo)])
+            .unwrap();
+        assert_eq!(contents(&list), vec!["alpha", "charlie"]);
+
+        list.undo(1).unwrap();
+        assert_eq!(contents(&list), vec!["alpha", "bravo", "charlie"]);
+        list.redo(1).unwrap();
+        assert_eq!(contents(&list), vec!["alpha", "charlie"]);
+    }
+
+    #[test]
+    fn undo_archive_moves_tasks_back() {
+        let path = temp_list("undo-archive");
+        let mut list = TodoList::open(&path).unwrap();
+        for content in ["alpha", "bravo", "charlie"] {
+            list.add(Task::new(Priority::NONE, content.to_string(), None))
+                .unwrap();
+        }
+        list.archive(|task| task.content != "bravo").unwrap();
+        assert_eq!(contents(&list), vec!["bravo"]);
+        assert_eq!(list.archived().unwrap().len(), 2);
+
+        list.undo(1).unwrap();
+        assert_eq!(contents(&list), vec!["alpha", "bravo", "charlie"]);
+        assert!(list.archived().unwrap().is_empty());
+
+        // 撤销后有新的操作时不能再重做
+        list.add(Task::new(Priority::NONE, "delta".to_string(), None))
+            .unwrap();
+        assert!(matches!(list.redo(1), Err(Error::NothingToRedo)));
+    }
+
+    #[test]
+    fn ids_are_not_reused() {
+        let path = temp_list("ids");
+        let mut list = TodoList::open(&path).unwrap();
+        list.add(Task::new(Priority::NONE, "alpha".to_string(), None))
+            .unwrap();
+        let bravo = list
+            .add(Task::new(Priority::NONE, "bravo".to_string(), None))
+            .unwrap();
+
+        // 删除编号最大的任务后，新任务不会得到它的编号
+        let task = list.get(bravo).unwrap().clone();
+        list.commit(Action::Delete, vec![Change::delete(&task)])
+            .unwrap();
+        let charlie = list
+            .add(Task::new(Priority::NONE, "charlie".to_string(), None))
+            .unwrap();
+        assert_eq!(charlie, bravo + 1);
+
+        // 归档的任务同样不再占用编号，但编号不会再分配
+        list.archive(|task| task.id == charlie).unwrap();
+        let delta = list
+            .add(Task::new(Priority::NONE, "delta".to_string(), None))
+            .unwrap();
+        assert_eq!(delta, charlie + 1);
+    }
+
+    #[test]
+    fn repair_can_be_undone() {
+        let path = temp_list("repair");
+        let original = "[A] alpha (2024-01-01) (id:1)\n【B】 bravo\nnonsense ((\n";
+        fs::write(&path, original).unwrap();
+        let mut list = TodoList::open(&path).unwrap();
+        assert_eq!(list.invalid_lines().len(), 2);
+
+        let repaired = list.repair().unwrap();
+        assert_eq!(repaired.len(), 2);
+        assert_eq!(repaired[0].1.stringify(), "[B] bravo (-) (id:2)");
+        assert_eq!(contents(&list), vec!["alpha", "bravo", "nonsense (("]);
+        let fixed = fs::read_to_string(&path).unwrap();
+
+        let entries = list.undo(1).unwrap();
+        assert!(entries[0].action == Action::Repair);
+        assert_eq!(fs::read_to_string(&path).unwrap(), original);
+        assert_eq!(list.invalid_lines().len(), 2);
+        list.redo(1).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), fixed);
+    }
+}
@@ -1,55 +1,199 @@
 mod cli;
-mod date;
-mod parser;
-mod priority;
-mod state;
+mod completion;
+mod config;
+mod editor;
+mod output;
 mod tasks;
+mod tui;
 
-use clap::Parser;
-use std::io::{Error, ErrorKind};
+use clap::{CommandFactory, FromArgMatches};
+use colored::Colorize;
+use std::env;
 use std::path::PathBuf;
+use std::process;
+use todotxt::{i18n, t, Error, ParseMode, Result, Task, TodoList};
+use unicode_width::UnicodeWidthStr;
 
 use cli::{Action, Args};
-use tasks::Task;
+use config::Config;
 
-fn config() -> (Action, Result<PathBuf, Error>) {
-    let args = Args::parse();
-    let file = args.file.or_else(get_default_file);
-    (
-        args.action,
-        file.ok_or(Error::new(ErrorKind::InvalidInput, "未指定任务清单文件")),
-    )
+/// 命令行参数优先于环境变量和配置文件
+fn config(conf: &Config) -> (Action, Result<TodoList>) {
+    let command = Args::command();
+    let builtin: Vec<&str> = command.get_subcommands().map(|c| c.get_name()).collect();
+    let args = conf.expand_alias(env::args_os().collect(), &builtin);
+    let args = Args::from_arg_matches(&cli::localize(command).get_matches_from(args))
+        .unwrap_or_else(|e| e.exit());
+    let file = args
+        .file
+        .or_else(|| conf.file.clone())
+        .or_else(get_default_file);
+    // 检查时总是原样保留无法解析的行，以便列出全部问题
+    let mode = if args.strict && !matches!(args.action, Action::Check { .. }) {
+        ParseMode::Strict
+    } else {
+        ParseMode::Tolerant
+    };
+    let list = match file {
+        Some(path) => TodoList::open_with(path, args.codec, mode),
+        None => Err(Error::InvalidInput(t!("error.no_file").to_string())),
+    };
+    let list = list.map(|mut list| {
+        if let Some(archive) = args.archive.or_else(|| conf.archive.clone()) {
+            list.set_archive_path(archive);
+        }
+        list.set_backup(conf.backup);
+        list
+    });
+    (args.action, list)
 }
 
-fn run(action: Action, file_path: PathBuf) -> Result<(), Error> {
+fn run(action: Action, mut list: TodoList) -> Result<()> {
+    let list = &mut list;
+    let defaults = config::get();
     match action {
         Action::Add {
             content,
             priority,
             due_to,
+            rec,
+            parent,
+            dep,
         } => {
-            let task = Task::new(priority.unwrap_or_default(), content, due_to);
-            tasks::add_task(&file_path, task)
+            let priority = priority.unwrap_or(defaults.priority);
+            let mut task = Task::new(priority, content, due_to.or(defaults.due_to));
+            task.recurrence = rec;
+            task.parent = parent;
+            task.deps = dep;
+            tasks::add_task(list, task)
+        }
+        Action::List { mode, format, conf } => {
+            tasks::list_tasks(list, &mode.unwrap_or(defaults.mode), &conf, format)
+        }
+        Action::Projects { mode } => tasks::list_projects(list, &mode.unwrap_or(defaults.mode)),
+        Action::Contexts { mode } => tasks::list_contexts(list, &mode.unwrap_or(defaults.mode)),
+        Action::Done { target, force } => tasks::complete_tasks(list, target, force),
+        Action::Modify { target, content } => tasks::modify_tasks(list, target, content),
+        Action::Start { target } => tasks::start_tasks(list, target),
+        Action::Wait { target } => tasks::wait_tasks(list, target),
+        Action::Remove { target } => tasks::remove_tasks(list, target),
+        Action::Delete { target } => tasks::delete_tasks(list, target),
+        Action::Undo { count } => tasks::undo_tasks(list, count),
+        Action::Redo { count } => tasks::redo_tasks(list, count),
+        Action::History { limit } => tasks::show_history(list, limit),
+        Action::Archive { state, older_than } => tasks::archive_tasks(list, &state, older_than),
+        Action::Check { fix } => tasks::check_tasks(list, fix),
+        Action::Edit { mode, conf } => {
+            editor::edit_tasks(list, &mode.unwrap_or(defaults.mode), &conf)
         }
-        Action::List { mode, conf } => tasks::list_tasks(&file_path, &mode, &conf),
-        Action::Done { conf } => tasks::complete_tasks(&file_path, &conf),
-        Action::Modify { conf } => tasks::modify_tasks(&file_path, &conf),
-        Action::Remove { conf } => tasks::remove_tasks(&file_path, &conf),
-        Action::Delete { conf } => tasks::delete_tasks(&file_path, &conf),
+        Action::Tui { mode, conf } => tui::run(list, conf, mode.unwrap_or(defaults.mode)),
+        Action::Export {
+            ical: _,
+            output,
+            mode,
+            conf,
+        } => tasks::export_tasks(list, &mode.unwrap_or(defaults.mode), &conf, output),
+        Action::Import { ical } => tasks::import_tasks(list, &ical),
+        Action::Completions { .. } | Action::Man => unreachable!("在打开任务清单之前处理"),
     }
 }
 
+/// 当前目录下的 todo.txt，不存在时使用主目录下的 todo.txt
 fn get_default_file() -> Option<PathBuf> {
+    let local = PathBuf::from("todo.txt");
+    if local.exists() {
+        return Some(local);
+    }
     home::home_dir().map(|mut path| {
         path.push("todo.txt");
         path
     })
 }
 
-fn main() -> Result<(), Error> {
-    let (action, file_path) = config();
+/// 各类错误的退出码，2 保留给命令行参数错误
+fn exit_code(e: &Error) -> i32 {
+    match e {
+        Error::InvalidInput(_) => 1,
+        Error::Io(_) => 3,
+        Error::Parse { .. } => 4,
+        Error::InvalidLine(_) => 5,
+        Error::UnknownField(_) => 6,
+        Error::InvalidDate(_) => 7,
+        Error::InvalidPriority(_) => 8,
+        Error::InvalidRecurrence(_) => 9,
+        Error::InvalidQuery(_) => 10,
+        Error::InvalidId(_) => 11,
+        Error::UnknownId(_) => 12,
+        Error::EmptyList => 13,
+        Error::Locked => 14,
+        Error::InvalidJournal(_) => 15,
+        Error::NothingToUndo => 16,
+        Error::NothingToRedo => 17,
+        Error::JournalConflict { .. } => 18,
+        Error::Unrepairable => 19,
+        Error::InvalidConfig(_) => 20,
+        Error::InvalidCalendar(_) => 21,
+        Error::DependencyCycle(_) => 22,
+        Error::Blocked { .. } => 23,
+    }
+}
 
-    run(action, file_path?)?;
+fn hint(e: &Error) -> Option<&'static str> {
+    match e {
+        Error::Parse { cause, .. } => hint(cause).or(Some(t!("hint.parse"))),
+        Error::InvalidLine(_) => Some(t!("hint.invalid_line")),
+        Error::UnknownField(_) => Some(t!("hint.unknown_field")),
+        Error::InvalidDate(_) => Some(t!("hint.invalid_date")),
+        Error::InvalidPriority(_) => Some(t!("hint.invalid_priority")),
+        Error::InvalidRecurrence(_) => Some(t!("hint.invalid_recurrence")),
+        Error::UnknownId(_) => Some(t!("hint.unknown_id")),
+        Error::EmptyList => Some(t!("hint.empty_list")),
+        Error::Locked => Some(t!("hint.locked")),
+        Error::JournalConflict { .. } => Some(t!("hint.journal_conflict")),
+        Error::Unrepairable => Some(t!("hint.unrepairable")),
+        Error::InvalidConfig(_) => Some(t!("hint.invalid_config")),
+        Error::InvalidCalendar(_) => Some(t!("hint.invalid_calendar")),
+        Error::DependencyCycle(_) => Some(t!("hint.dependency_cycle")),
+        Error::Blocked { .. } => Some(t!("hint.blocked")),
+        _ => None,
+    }
+}
 
-    Ok(())
+fn report(e: &Error) {
+    eprintln!("{} {}", "==>".red(), e);
+    if let Error::Parse { column, text, .. } = e {
+        // 按显示宽度对齐，使脱字符指向出错的字符
+        let prefix: String = text.chars().take(column - 1).collect();
+        eprintln!("    {}", text);
+        eprintln!("    {}{}", " ".repeat(prefix.width()), "^".red());
+    }
+    if let Some(hint) = hint(e) {
+        eprintln!("{} {}", "==>".yellow(), hint);
+    }
+}
+
+fn fail(e: Error) -> ! {
+    report(&e);
+    process::exit(exit_code(&e));
+}
+
+fn main() {
+    // 先按环境变量选择语言，使读取配置时的错误也能本地化，再按配置文件中的语言重新选择
+    i18n::set_catalog(config::catalog(config::env_locale().as_deref()).unwrap_or_else(|e| fail(e)));
+    let conf = Config::load().unwrap_or_else(|e| fail(e));
+    if let Some(locale) = &conf.locale {
+        i18n::set_catalog(config::catalog(Some(locale)).unwrap_or_else(|e| fail(e)));
+    }
+    config::init(conf);
+    completion::complete();
+
+    let (action, list) = config(config::get());
+    let result = match action {
+        Action::Completions { shell } => completion::print_completions(&shell),
+        Action::Man => completion::print_man(),
+        action => list.and_then(|list| run(action, list)),
+    };
+    if let Err(e) = result {
+        fail(e);
+    }
 }
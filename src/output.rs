@@ -0,0 +1,313 @@
+use clap::builder::PossibleValue;
+use clap::ValueEnum;
+use colored::{ColoredString, Colorize};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{Result, Write};
+use todotxt::{t, Date, State, Task};
+
+use crate::config::{self, Theme};
+
+/// 带颜色的任务，用于在终端中显示，颜色和日期格式取自配置
+pub struct Styled<'a>(pub &'a Task);
+
+impl Styled<'_> {
+    fn fmt_due_to(&self, theme: &Theme) -> Option<ColoredString> {
+        let task = self.0;
+        let due_to = task.due_to.map(fmt_date)?;
+        match task.state {
+            State::Completed => Some(
+                due_to.color(
+                    if task
+                        .completed_at
+                        .is_some_and(|date| date > task.due_to.unwrap())
+                    {
+                        theme.late
+                    } else {
+                        theme.done
+                    },
+                ),
+            ),
+            State::Removed => Some(due_to.dimmed()),
+            _ => Some(due_to.color(if task.due_to.unwrap().is_over() {
+                theme.overdue
+            } else {
+                theme.due
+            })),
+        }
+    }
+
+    fn fmt_completed_at(&self, theme: &Theme) -> Option<ColoredString> {
+        self.0
+            .completed_at
+            .map(|date| fmt_date(date).color(theme.done))
+    }
+}
+
+impl fmt::Display for Styled<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let theme = &config::get().theme;
+        let task = self.0;
+        let state = task.state.as_str();
+        let priority = task.priority.as_str();
+        let content = task.content.as_str();
+        let created_at = task.created_at.map_or("-".to_string(), fmt_date);
+        if task.state == State::Removed {
+            write!(
+                f,
+                "{}",
+                format!("{state}[{priority}] {content} ({created_at})").dimmed()
+            )?;
+            if let Some(due_to) = self.fmt_due_to(theme) {
+                write!(f, " {}", format!("(due:{due_to})").dimmed())?;
+            }
+            if let Some(recurrence) = task.recurrence {
+                write!(f, " {}", format!("(rec:{recurrence})").dimmed())?;
+            }
+            if let Some(deps) = fmt_deps(task) {
+                write!(f, " {}", format!("(dep:{deps})").dimmed())?;
+            }
+        } else {
+            write!(
+                f,
+                "{}[{}] {} ({})",
+                state.color(theme.state_color(&task.state)),
+                priority.color(theme.priority),
+                content,
+                created_at.color(theme.created)
+            )?;
+            if let Some(due_to) = self.fmt_due_to(theme) {
+                write!(f, " (due:{due_to})")?;
+            }
+            if let Some(recurrence) = task.recurrence {
+                write!(
+                    f,
+                    " (rec:{})",
+                    recurrence.to_string().color(theme.recurrence)
+                )?;
+            }
+            if let Some(deps) = fmt_deps(task) {
+                write!(f, " (dep:{})", deps)?;
+            }
+            if let Some(completed_at) = self.fmt_completed_at(theme) {
+                write!(f, " ({completed_at})")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn fmt_deps(task: &Task) -> Option<String> {
+    let deps: Vec<String> = task.deps.iter().map(|id| id.to_string()).collect();
+    Some(deps.join(",")).filter(|deps| !deps.is_empty())
+}
+
+/// 按配置的日期格式显示日期，机器可读的输出格式不受影响
+pub fn fmt_date(date: Date) -> String {
+    config::get()
+        .date_format
+        .as_deref()
+        .and_then(|format| date.format(format))
+        .unwrap_or_else(|| date.fmt())
+}
+
+/// `list` 的输出格式
+#[derive(Clone, Copy, PartialEq)]
+pub enum Format {
+    /// 带颜色的文本
+    Human,
+    /// JSON 数组
+    Json,
+    /// 每行一个 JSON 对象
+    Jsonl,
+    /// 带表头的 CSV
+    Csv,
+    /// 带表头的 TSV
+    Tsv,
+    /// 稳定的制表符分隔格式，不带表头，空字段为 `-`，内容在最后一列
+    Porcelain,
+}
+
+impl ValueEnum for Format {
+    fn value_variants<'a>() -> &'a [Self] {
+        &[
+            Self::Human,
+            Self::Json,
+            Self::Jsonl,
+            Self::Csv,
+            Self::Tsv,
+            Self::Porcelain,
+        ]
+    }
+
+    /// 取值的说明随界面语言变化，因此不使用派生
+    fn to_possible_value(&self) -> Option<PossibleValue> {
+        let (name, help) = match self {
+            Self::Human => ("human", t!("help.format.human")),
+            Self::Json => ("json", t!("help.format.json")),
+            Self::Jsonl => ("jsonl", t!("help.format.jsonl")),
+            Self::Csv => ("csv", t!("help.format.csv")),
+            Self::Tsv => ("tsv", t!("help.format.tsv")),
+            Self::Porcelain => ("porcelain", t!("help.format.porcelain")),
+        };
+        Some(PossibleValue::new(name).help(help))
+    }
+}
+
+const COLUMNS: [&str; 15] = [
+    "id",
+    "line",
+    "state",
+    "priority",
+    "content",
+    "created_at",
+    "due_to",
+    "completed_at",
+    "recurrence",
+    "tags",
+    "projects",
+    "contexts",
+    "parent",
+    "deps",
+    "uid",
+];
+
+pub fn write_tasks(writer: &mut impl Write, tasks: &[&Task], format: Format) -> Result<()> {
+    match format {
+        Format::Human => {
+            for task in tasks {
+                // 归档中的任务没有编号
+                if task.id == 0 {
+                    writeln!(writer, "{:>3} {}", "-", Styled(task))?;
+                } else {
+                    writeln!(writer, "{:3} {}", task.id, Styled(task))?;
+                }
+            }
+        }
+        Format::Json => {
+            let tasks: Vec<Value> = tasks.iter().map(|task| to_json(task)).collect();
+            serde_json::to_writer_pretty(&mut *writer, &tasks)?;
+            writeln!(writer)?;
+        }
+        Format::Jsonl => {
+            for task in tasks {
+                serde_json::to_writer(&mut *writer, &to_json(task))?;
+                writeln!(writer)?;
+            }
+        }
+        Format::Csv => {
+            writeln!(writer, "{}", COLUMNS.join(","))?;
+            for task in tasks {
+                let row: Vec<String> = to_row(task).iter().map(|v| csv_escape(v)).collect();
+                writeln!(writer, "{}", row.join(","))?;
+            }
+        }
+        Format::Tsv => {
+            writeln!(writer, "{}", COLUMNS.join("\t"))?;
+            for task in tasks {
+                let row: Vec<String> = to_row(task).iter().map(|v| v.replace('\t', " ")).collect();
+                writeln!(writer, "{}", row.join("\t"))?;
+            }
+        }
+        Format::Porcelain => {
+            for task in tasks {
+                let mut row: Vec<String> = to_row(task)
+                    .into_iter()
+                    .map(|v| {
+                        if v.is_empty() {
+                            "-".to_string()
+                        } else {
+                            v.replace('\t', " ")
+                        }
+                    })
+                    .collect();
+                // 内容可能包含任意字符，放在最后一列
+                let content = row.remove(4);
+                row.push(content);
+                writeln!(writer, "{}", row.join("\t"))?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// 以缩进表示层级的任务树，父任务之后显示已完成和全部子任务数
+pub fn write_tree(
+    writer: &mut impl Write,
+    rows: &[(usize, &Task)],
+    progress: &HashMap<usize, (usize, usize)>,
+) -> Result<()> {
+    for (depth, task) in rows {
+        let indent = "  ".repeat(*depth);
+        // 归档中的任务没有编号
+        if task.id == 0 {
+            write!(writer, "{:>3} {}{}", "-", indent, Styled(task))?;
+        } else {
+            write!(writer, "{:3} {}{}", task.id, indent, Styled(task))?;
+        }
+        match progress.get(&task.id).filter(|_| task.id != 0) {
+            Some((done, total)) => writeln!(writer, " {}", format!("({done}/{total})").cyan())?,
+            None => writeln!(writer)?,
+        }
+    }
+    Ok(())
+}
+
+fn to_json(task: &Task) -> Value {
+    json!({
+        "id": task.id,
+        "line": task.line,
+        "state": task.state.name(),
+        "priority": task.priority.level().map(|_| task.priority.as_str()),
+        "content": task.content,
+        "created_at": task.created_at.map(|date| date.fmt()),
+        "due_to": task.due_to.map(|date| date.fmt()),
+        "completed_at": task.completed_at.map(|date| date.fmt()),
+        "recurrence": task.recurrence.map(|rec| rec.to_string()),
+        "parent": task.parent,
+        "deps": task.deps,
+        "uid": task.uid,
+        "tags": task.tags,
+        "projects": task.projects,
+        "contexts": task.contexts,
+    })
+}
+
+/// 按 [`COLUMNS`] 的顺序排列的字段，列表字段以空格分隔
+fn to_row(task: &Task) -> Vec<String> {
+    vec![
+        task.id.to_string(),
+        task.line.to_string(),
+        task.state.name().to_string(),
+        task.priority
+            .level()
+            .map(|_| task.priority.to_string())
+            .unwrap_or_default(),
+        task.content.clone(),
+        task.created_at.map(|date| date.fmt()).unwrap_or_default(),
+        task.due_to.map(|date| date.fmt()).unwrap_or_default(),
+        task.completed_at.map(|date| date.fmt()).unwrap_or_default(),
+        task.recurrence
+            .map(|rec| rec.to_string())
+            .unwrap_or_default(),
+        task.tags.join(" "),
+        task.projects.join(" "),
+        task.contexts.join(" "),
+        task.parent.map(|id| id.to_string()).unwrap_or_default(),
+        task.deps
+            .iter()
+            .map(|id| id.to_string())
+            .collect::<Vec<_>>()
+            .join(" "),
+        task.uid.clone().unwrap_or_default(),
+    ]
+}
+
+fn csv_escape(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
@@ -1,51 +1,201 @@
 use regex::Regex;
+use std::collections::HashSet;
 use std::fs::File;
-use std::io::{BufRead, BufReader, Error, ErrorKind, Result};
-use std::path::PathBuf;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
 
+use crate::codec::{Codec, TodoTxtParser};
 use crate::date::Date;
+use crate::error::{Error, Result};
 use crate::priority::Priority;
 use crate::state::State;
-use crate::tasks::Task;
+use crate::t;
+use crate::task::Task;
 
-fn parse_line(line: &str, regexes: &[&Regex]) -> Result<Task> {
-    let caps = regexes[0]
-        .captures(line)
-        .ok_or_else(|| Error::new(ErrorKind::InvalidData, "无效的任务格式"))?;
+/// 单行任务的解析器，预先编译好所需的正则表达式
+pub struct LineParser {
+    re_line: Regex,
+    re_field: Regex,
+    re_tag: Regex,
+    re_project: Regex,
+    re_context: Regex,
+    re_priority: Regex,
+    re_created: Regex,
+}
 
-    let state: State = match line.chars().next() {
-        Some('✓') => State::Completed,
-        Some('✗') => State::Removed,
-        _ => State::Pending,
-    };
-    let priority: Priority = caps[1].parse()?;
-    let content = caps[2].to_string();
-    let created_at: Date = caps[3].parse()?;
+impl LineParser {
+    pub fn new() -> Self {
+        Self {
+            re_line: Regex::new(concat!(
+                r"^(?:([▶⏸✓✗]) )?",
+                r"\[(.)\] ",
+                r"(.+?) ",
+                r"\((\d{4}-\d{2}-\d{2}|-)\)",
+                r"((?: \([^()]*\))*)\s*$",
+            ))
+            .unwrap(),
+            re_field: Regex::new(r" \((?:(\w+):)?([^()]*)\)").unwrap(),
+            re_tag: tag_regex(),
+            re_project: project_regex(),
+            re_context: context_regex(),
+            re_priority: Regex::new(r"^\[.\] ").unwrap(),
+            re_created: Regex::new(r" \((?:\d{4}-\d{2}-\d{2}|-)\)").unwrap(),
+        }
+    }
 
-    let due_to = if state == State::Completed && caps.get(5).is_none() {
-        None // 当任务已完成时，第四个捕获组必是完成日期
-    } else {
-        caps.get(4).map(|s| s.as_str().parse()).transpose()?
-    };
+    pub fn parse_line(&self, line: &str) -> LineResult<Task> {
+        let regexes = [
+            &self.re_line,
+            &self.re_field,
+            &self.re_tag,
+            &self.re_project,
+            &self.re_context,
+            &self.re_priority,
+            &self.re_created,
+        ];
+        parse_line(line, &regexes)
+    }
+}
 
-    let completed_at: Option<Date> = if state == State::Completed {
-        caps.get(if caps.get(5).is_some() { 5 } else { 4 })
-            .and_then(|s| s.as_str().parse().ok())
-    } else {
-        None
-    };
+impl Default for LineParser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub fn tag_regex() -> Regex {
+    Regex::new(r"(?:\s|^)#(\w+)(?:\s|$)").unwrap()
+}
+
+pub fn project_regex() -> Regex {
+    Regex::new(r"(?:\s|^)\+(\S+)").unwrap()
+}
+
+pub fn context_regex() -> Regex {
+    Regex::new(r"(?:\s|^)@(\S+)").unwrap()
+}
+
+/// 单行任务的解析错误及其位置
+#[derive(Debug)]
+pub struct LineError {
+    /// 出错的列号，按字符计数，从 1 开始
+    pub column: usize,
+    pub error: Error,
+}
 
-    let tags = parse_tags(line, regexes[1]);
+pub type LineResult<T> = std::result::Result<T, LineError>;
 
-    Ok(Task {
+impl LineError {
+    /// `offset` 为出错处在行中的字节偏移量
+    pub fn at(line: &str, offset: usize, error: Error) -> Self {
+        let column = line[..offset.min(line.len())].chars().count() + 1;
+        Self { column, error }
+    }
+}
+
+fn parse_line(line: &str, regexes: &[&Regex]) -> LineResult<Task> {
+    let caps = regexes[0].captures(line).ok_or_else(|| {
+        let (offset, reason) = diagnose(line, regexes[5], regexes[6]);
+        LineError::at(line, offset, Error::InvalidLine(reason))
+    })?;
+    let at = |offset: usize| move |error: Error| LineError::at(line, offset, error);
+
+    let state: State = caps
+        .get(1)
+        .map_or("", |s| s.as_str())
+        .parse()
+        .map_err(at(0))?;
+    // 本程序的格式一直以 `[O]` 表示没有优先级，优先级 O 写为 `[o]`
+    let priority: Priority = match &caps[2] {
+        "O" => Priority::NONE,
+        "o" => Priority::O,
+        s => s.parse().map_err(at(caps.get(2).unwrap().start()))?,
+    };
+    let content = caps[3].to_string();
+    // 创建日期为 `-` 表示没有创建日期
+    let created_at = match &caps[4] {
+        "-" => None,
+        s => Some(parse_date(s).map_err(at(caps.get(4).unwrap().start()))?),
+    };
+
+    let mut task = Task {
+        id: 0,
+        line: 0,
         state,
         priority,
         content,
         created_at,
-        due_to,
-        completed_at,
-        tags,
-    })
+        due_to: None,
+        completed_at: None,
+        recurrence: None,
+        parent: None,
+        deps: Vec::new(),
+        uid: None,
+        tags: parse_tags(&caps[3], regexes[2]),
+        projects: parse_tags(&caps[3], regexes[3]),
+        contexts: parse_tags(&caps[3], regexes[4]),
+    };
+
+    // 创建日期之后是若干个括号包裹的字段，不带键的日期为完成日期
+    let fields_start = caps.get(5).unwrap().start();
+    for field in regexes[1].captures_iter(&caps[5]) {
+        let value = &field[2];
+        let at = at(fields_start + field.get(2).unwrap().start());
+        match field.get(1).map(|key| key.as_str()) {
+            None => task.completed_at = Some(parse_date(value).map_err(at)?),
+            Some("due") => task.due_to = Some(parse_date(value).map_err(at)?),
+            Some("rec") => task.recurrence = Some(value.parse().map_err(at)?),
+            Some("parent") => task.parent = Some(parse_id(value).map_err(at)?),
+            Some("dep") => task.deps.extend(parse_ids(value).map_err(at)?),
+            Some("uid") => task.uid = Some(value.to_string()).filter(|uid| !uid.is_empty()),
+            Some("id") => task.id = parse_id(value).map_err(at)?,
+            Some(key) => {
+                let offset = fields_start + field.get(1).unwrap().start();
+                return Err(LineError::at(
+                    line,
+                    offset,
+                    Error::UnknownField(key.to_string()),
+                ));
+            }
+        }
+    }
+    if task.state != State::Completed {
+        task.completed_at = None;
+    }
+
+    Ok(task)
+}
+
+/// 推测一行不符合格式的原因，返回出错处的字节偏移量及原因
+fn diagnose(line: &str, re_priority: &Regex, re_created: &Regex) -> (usize, String) {
+    let rest = line.trim_start_matches(['▶', '⏸', '✓', '✗', ' ']);
+    if !re_priority.is_match(rest) {
+        (
+            line.len() - rest.len(),
+            t!("parser.missing_priority").to_string(),
+        )
+    } else if !re_created.is_match(rest) {
+        (line.len(), t!("parser.missing_created").to_string())
+    } else {
+        (0, t!("parser.invalid_format").to_string())
+    }
+}
+
+/// 任务清单中的日期只接受 `YYYY-MM-DD` 格式
+pub fn parse_date(s: &str) -> Result<Date> {
+    Date::parse_exact(s).ok_or_else(|| Error::InvalidDate(s.to_string()))
+}
+
+/// 以逗号分隔的任务编号，如 `3,5`
+pub fn parse_ids(s: &str) -> Result<Vec<usize>> {
+    s.split(',').map(parse_id).collect()
+}
+
+pub fn parse_id(s: &str) -> Result<usize> {
+    match s.parse() {
+        Ok(id) if id > 0 => Ok(id),
+        _ => Err(Error::InvalidId(s.to_string())),
+    }
 }
 
 pub fn parse_tags(content: &str, re: &Regex) -> Vec<String> {
@@ -54,24 +204,252 @@ pub fn parse_tags(content: &str, re: &Regex) -> Vec<String> {
         .collect()
 }
 
-pub fn parse_file(file_path: &PathBuf) -> Result<Vec<Task>> {
-    let re_line = Regex::new(concat!(
-        r"\[(.)\] ",
-        r"(.+?) ",
-        r"\((\d{4}-\d{2}-\d{2})\)",
-        r"(?: \(due:(\d{4}-\d{2}-\d{2})\))?",
-        r"(?: \((\d{4}-\d{2}-\d{2})\))?",
-    ))
-    .unwrap();
-    let re_tag = Regex::new(r"(?:\s|^)#(\w+)(?:\s|$)").unwrap();
+/// 遇到无法解析的行时的处理方式
+#[derive(Clone, Copy, PartialEq)]
+pub enum ParseMode {
+    /// 报告第一个无法解析的行并中止
+    Strict,
+    /// 原样保留无法解析的行并记录原因
+    Tolerant,
+}
+
+/// 无法解析的行，写回文件时原样保留
+#[derive(Clone)]
+pub struct InvalidLine {
+    /// 行号，从 1 开始
+    pub line_no: usize,
+    /// 出错的列号，从 1 开始
+    pub column: usize,
+    /// 在它之前的任务数，写回时据此放回原来的位置
+    pub position: usize,
+    pub text: String,
+    pub reason: String,
+}
+
+/// 解析后的任务清单
+#[derive(Default)]
+pub struct Document {
+    pub tasks: Vec<Task>,
+    pub invalid: Vec<InvalidLine>,
+}
+
+pub fn parse_line_with(codec: Codec, line: &str) -> LineResult<Task> {
+    match codec {
+        Codec::Native => LineParser::new().parse_line(line),
+        Codec::Todotxt => TodoTxtParser::new().parse_line(line),
+    }
+}
+
+/// 常见格式错误的修复器，预先编译好所需的正则表达式
+pub struct Repairer {
+    codec: Codec,
+    re_date: Regex,
+    re_mark: Regex,
+    re_empty_priority: Regex,
+    re_priority: Regex,
+    re_has_priority: Regex,
+    re_prefix: Regex,
+    re_has_created: Regex,
+    re_fields: Regex,
+}
+
+impl Repairer {
+    pub fn new(codec: Codec) -> Self {
+        Self {
+            codec,
+            re_date: Regex::new(r"(\d{4})[-/.](\d{1,2})[-/.](\d{1,2})").unwrap(),
+            re_mark: Regex::new(r"^([✓✗])\s*").unwrap(),
+            re_empty_priority: Regex::new(r"^((?:[✓✗] )?)\[\s*\]\s*").unwrap(),
+            re_priority: Regex::new(r"^((?:[✓✗] )?)\[(.)\]\s*").unwrap(),
+            re_has_priority: Regex::new(r"^(?:[✓✗] )?\[.\] ").unwrap(),
+            re_prefix: Regex::new(r"^((?:[✓✗] )?)").unwrap(),
+            re_has_created: Regex::new(r" \((?:\d{4}-\d{2}-\d{2}|-)\)").unwrap(),
+            re_fields: Regex::new(r"^(.*?)((?: \(\w+:[^()]*\))*)$").unwrap(),
+        }
+    }
+
+    /// 尝试修复常见的格式错误，修复后仍无法解析时返回 None
+    pub fn repair_line(&self, line: &str) -> Option<Task> {
+        let mut s = line.trim().to_string();
+        for (from, to) in [
+            ('（', "("),
+            ('）', ")"),
+            ('［', "["),
+            ('］', "]"),
+            ('【', "["),
+            ('】', "]"),
+            ('：', ":"),
+        ] {
+            s = s.replace(from, to);
+        }
+        // 日期使用其他分隔符或未补零，如 2024/6/7
+        s = self
+            .re_date
+            .replace_all(&s, |caps: &regex::Captures| {
+                format!("{}-{:0>2}-{:0>2}", &caps[1], &caps[2], &caps[3])
+            })
+            .into_owned();
+
+        if self.codec == Codec::Native {
+            // 状态标记后缺少空格、优先级为空或缺失
+            s = self.re_mark.replace(&s, "$1 ").into_owned();
+            s = self.re_empty_priority.replace(&s, "${1}[-] ").into_owned();
+            s = self.re_priority.replace(&s, "${1}[$2] ").into_owned();
+            if !self.re_has_priority.is_match(&s) {
+                s = self.re_prefix.replace(&s, "${1}[-] ").into_owned();
+            }
+            // 缺少创建日期时记为没有创建日期，不凭空补上
+            if !self.re_has_created.is_match(&s) {
+                s = self.re_fields.replace(&s, "${1} (-)${2}").into_owned();
+            }
+        }
 
-    let regexes = [&re_line, &re_tag];
+        parse_line_with(self.codec, &s).ok()
+    }
+}
+
+pub fn parse_file(file_path: &Path, codec: Codec, mode: ParseMode) -> Result<Document> {
+    let native = LineParser::new();
+    let todotxt = TodoTxtParser::new();
     let reader = BufReader::new(File::open(file_path)?);
-    let tasks = reader
-        .lines()
-        .filter_map(|line| line.ok().filter(|line| !line.is_empty()))
-        .map(|line| parse_line(&line, &regexes))
-        .collect::<Result<Vec<_>>>()?;
 
-    Ok(tasks)
+    let mut doc = Document::default();
+    for (i, line) in reader.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let result = match codec {
+            Codec::Native => native.parse_line(&line),
+            Codec::Todotxt => todotxt.parse_line(&line),
+        };
+        match result {
+            Ok(mut task) => {
+                task.line = i + 1;
+                doc.tasks.push(task);
+            }
+            Err(e) if mode == ParseMode::Tolerant => doc.invalid.push(InvalidLine {
+                line_no: i + 1,
+                column: e.column,
+                position: doc.tasks.len(),
+                text: line,
+                reason: e.error.to_string(),
+            }),
+            Err(e) => {
+                return Err(Error::Parse {
+                    path: file_path.to_path_buf(),
+                    line: i + 1,
+                    column: e.column,
+                    text: line,
+                    cause: Box::new(e.error),
+                })
+            }
+        }
+    }
+
+    Ok(doc)
+}
+
+/// 是否有任务没有编号（如旧版本写入）或编号重复
+pub fn missing_ids(tasks: &[Task]) -> bool {
+    let mut seen = HashSet::new();
+    tasks
+        .iter()
+        .any(|task| task.id == 0 || !seen.insert(task.id))
+}
+
+/// 为没有编号或编号重复的任务依次分配从 `next_id` 开始的新编号，写回文件后即固定下来
+pub fn assign_ids(tasks: &mut [Task], mut next_id: usize) {
+    let mut seen = HashSet::new();
+    for task in tasks.iter_mut() {
+        if task.id == 0 || !seen.insert(task.id) {
+            task.id = next_id;
+            next_id += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs;
+    use std::process;
+
+    fn repair(line: &str) -> Option<String> {
+        Repairer::new(Codec::Native)
+            .repair_line(line)
+            .map(|task| task.stringify())
+    }
+
+    #[test]
+    fn tolerant_parse_keeps_invalid_lines() {
+        let dir = env::temp_dir().join(format!("tdt-parser-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("todo.txt");
+        fs::write(
+            &path,
+            "[A] alpha (2024-01-01) (id:1)\nbroken\n\n[B] bravo (-) (id:2)\n",
+        )
+        .unwrap();
+
+        let doc = parse_file(&path, Codec::Native, ParseMode::Tolerant).unwrap();
+        assert_eq!(doc.tasks.len(), 2);
+        assert_eq!(doc.tasks[1].line, 4);
+        assert_eq!(doc.invalid.len(), 1);
+        let invalid = &doc.invalid[0];
+        assert_eq!((invalid.line_no, invalid.column), (2, 1));
+        assert_eq!(invalid.position, 1);
+        assert_eq!(invalid.text, "broken");
+
+        let err = parse_file(&path, Codec::Native, ParseMode::Strict);
+        assert!(matches!(err, Err(Error::Parse { line: 2, .. })));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn diagnose_points_at_the_problem() {
+        let parser = LineParser::new();
+        let error = |line: &str| {
+            let Err(e) = parser.parse_line(line) else {
+                panic!("{}", line);
+            };
+            (e.column, e.error.to_string())
+        };
+        assert_eq!(
+            error("✓ alpha (2024-01-01)"),
+            (
+                3,
+                Error::InvalidLine(t!("parser.missing_priority").to_string()).to_string()
+            )
+        );
+        assert_eq!(
+            error("[A] alpha"),
+            (
+                10,
+                Error::InvalidLine(t!("parser.missing_created").to_string()).to_string()
+            )
+        );
+        // 格式正确但取值无效时指向取值
+        assert_eq!(error("[A] alpha (2024-13-01)").0, 12);
+    }
+
+    #[test]
+    fn repair_fixes_common_mistakes() {
+        assert_eq!(
+            repair("【A】 alpha （2024/6/7）").as_deref(),
+            Some("[A] alpha (2024-06-07)")
+        );
+        assert_eq!(
+            repair("✓[] done (2024-01-01) (2024-01-02)").as_deref(),
+            Some("✓ [-] done (2024-01-01) (2024-01-02)")
+        );
+        // 缺少创建日期时不凭空补上，字段保持在创建日期之后
+        assert_eq!(
+            repair("[B]bravo (due:2024-02-01)").as_deref(),
+            Some("[B] bravo (-) (due:2024-02-01)")
+        );
+        assert_eq!(repair("plain").as_deref(), Some("[-] plain (-)"));
+        assert!(repair("[A] alpha (2024-01-01) (bad:1)").is_none());
+    }
 }
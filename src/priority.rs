@@ -1,51 +1,66 @@
 use std::cmp::Ordering;
 use std::fmt;
-use std::io::Error;
 use std::str::FromStr;
 
-#[derive(Clone, Copy, PartialEq, Eq)]
-pub enum Priority {
-    A,
-    B,
-    C,
-    O,
-}
+use crate::error::Error;
 
-impl Default for Priority {
-    fn default() -> Self {
-        Self::O
-    }
-}
+/// 按从高到低排列的优先级字母
+const LETTERS: &str = "ABCDEFGHIJKLMNOPQRSTUVWXYZ";
+
+/// 最多的优先级等级数
+pub const MAX_LEVELS: usize = LETTERS.len();
+
+/// 任务的优先级，A 最高、Z 最低，[`Priority::NONE`] 表示没有优先级
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Priority(char);
 
 impl Priority {
+    pub const A: Self = Self('A');
+    pub const B: Self = Self('B');
+    pub const C: Self = Self('C');
+    pub const O: Self = Self('O');
+    /// 没有优先级，写为 `-`
+    pub const NONE: Self = Self('-');
+
+    /// 前 `levels` 个优先级，从高到低
+    pub fn levels(levels: usize) -> impl Iterator<Item = Self> {
+        LETTERS.chars().take(levels).map(Self)
+    }
+
+    /// 优先级的等级，A 为 1，没有优先级时为 `None`
+    pub fn level(&self) -> Option<usize> {
+        LETTERS.find(self.0).map(|i| i + 1)
+    }
+
     pub fn as_str(&self) -> &str {
-        match self {
-            Self::A => "A",
-            Self::B => "B",
-            Self::C => "C",
-            Self::O => "O",
+        match self.level() {
+            Some(level) => &LETTERS[level - 1..level],
+            None => "-",
         }
     }
 }
 
+impl Default for Priority {
+    fn default() -> Self {
+        Self::NONE
+    }
+}
+
 impl fmt::Display for Priority {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        match self {
-            Self::A => f.write_str("A"),
-            Self::B => f.write_str("B"),
-            Self::C => f.write_str("C"),
-            Self::O => f.write_str("O"),
-        }
+        f.write_str(self.as_str())
     }
 }
 
-impl From<char> for Priority {
-    fn from(c: char) -> Self {
+/// 不区分大小写，`-` 表示没有优先级，其他 A-Z 以外的字符为无效的优先级
+impl TryFrom<char> for Priority {
+    type Error = Error;
+
+    fn try_from(c: char) -> Result<Self, Self::Error> {
         match c.to_ascii_uppercase() {
-            'A' => Self::A,
-            'B' => Self::B,
-            'C' => Self::C,
-            _ => Self::O,
+            c @ 'A'..='Z' => Ok(Self(c)),
+            '-' => Ok(Self::NONE),
+            _ => Err(Error::InvalidPriority(c.to_string())),
         }
     }
 }
@@ -54,11 +69,12 @@ impl FromStr for Priority {
     type Err = Error;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        match s.to_ascii_uppercase().as_str() {
-            "A" => Ok(Self::A),
-            "B" => Ok(Self::B),
-            "C" => Ok(Self::C),
-            _ => Ok(Self::O),
+        let mut chars = s.chars();
+        match (chars.next(), chars.next()) {
+            (Some(c), None) => c
+                .try_into()
+                .map_err(|_| Error::InvalidPriority(s.to_string())),
+            _ => Err(Error::InvalidPriority(s.to_string())),
         }
     }
 }
@@ -71,13 +87,9 @@ impl PartialOrd for Priority {
 
 impl Ord for Priority {
     fn cmp(&self, other: &Self) -> Ordering {
-        fn priority_value(priority: &Priority) -> u8 {
-            match priority {
-                Priority::A => 3,
-                Priority::B => 2,
-                Priority::C => 1,
-                Priority::O => 0,
-            }
+        /// 没有优先级为 0，其余优先级越高值越大
+        fn priority_value(priority: &Priority) -> usize {
+            priority.level().map_or(0, |level| MAX_LEVELS + 1 - level)
         }
         priority_value(self).cmp(&priority_value(other))
     }
@@ -0,0 +1,457 @@
+use std::collections::HashSet;
+
+use crate::date::Date;
+use crate::error::{Error, Result};
+use crate::priority::Priority;
+use crate::state::State;
+use crate::t;
+use crate::task::Task;
+
+/// 比较条件，`Range` 的两端都包含在内
+#[derive(Clone, Copy)]
+pub enum Cmp<T> {
+    Eq(T),
+    Ne(T),
+    Lt(T),
+    Le(T),
+    Gt(T),
+    Ge(T),
+    Range(T, T),
+}
+
+impl<T: Ord + Copy> Cmp<T> {
+    fn test(&self, value: T) -> bool {
+        match *self {
+            Self::Eq(v) => value == v,
+            Self::Ne(v) => value != v,
+            Self::Lt(v) => value < v,
+            Self::Le(v) => value <= v,
+            Self::Gt(v) => value > v,
+            Self::Ge(v) => value >= v,
+            Self::Range(from, to) => from <= value && value <= to,
+        }
+    }
+}
+
+/// 日期比较作用的字段
+#[derive(Clone, Copy)]
+pub enum DateField {
+    Due,
+    Created,
+    Completed,
+}
+
+/// `has:` 检查是否存在的字段
+#[derive(Clone, Copy)]
+pub enum HasField {
+    Due,
+    Rec,
+    Tag,
+    Project,
+    Context,
+}
+
+/// 查询语句的语法树
+///
+/// ```text
+/// tag:rust and (pri>=B or due<+3d) and not @home
+/// ```
+pub enum Query {
+    All,
+    And(Box<Query>, Box<Query>),
+    Or(Box<Query>, Box<Query>),
+    Not(Box<Query>),
+    /// 内容中包含的文本
+    Text(String),
+    Tag(String),
+    Project(String),
+    Context(String),
+    /// 优先级比较，A 最高，`pri>=B` 表示 B 或更高
+    Priority(Cmp<Priority>),
+    /// 日期比较，没有该日期的任务不匹配
+    Date(DateField, Cmp<Date>),
+    Id(Cmp<usize>),
+    State(State),
+    Has(HasField),
+    /// 编号在集合中的任务，用于 `--blocked` 等与其他任务相关、无法由单个任务判断的条件
+    Ids(HashSet<usize>),
+}
+
+impl Query {
+    /// 解析查询语句，空语句匹配所有任务
+    pub fn parse(s: &str) -> Result<Query> {
+        let tokens = tokenize(s)?;
+        let mut parser = QueryParser { tokens, pos: 0 };
+        if parser.tokens.is_empty() {
+            return Ok(Query::All);
+        }
+        let query = parser.parse_or()?;
+        match parser.tokens.get(parser.pos) {
+            Some(token) => Err(invalid(t!("query.extra", token.describe()))),
+            None => Ok(query),
+        }
+    }
+
+    /// 与另一个条件组合，`All` 不产生多余的节点
+    pub fn and(self, other: Query) -> Query {
+        match (self, other) {
+            (Query::All, query) | (query, Query::All) => query,
+            (a, b) => Query::And(Box::new(a), Box::new(b)),
+        }
+    }
+
+    pub fn matches(&self, task: &Task) -> bool {
+        match self {
+            Self::All => true,
+            Self::And(a, b) => a.matches(task) && b.matches(task),
+            Self::Or(a, b) => a.matches(task) || b.matches(task),
+            Self::Not(q) => !q.matches(task),
+            Self::Text(text) => task.content.contains(text.as_str()),
+            Self::Tag(tag) => task.tags.contains(tag),
+            Self::Project(project) => task.projects.contains(project),
+            Self::Context(context) => task.contexts.contains(context),
+            Self::Priority(cmp) => cmp.test(task.priority),
+            Self::Date(field, cmp) => {
+                let date = match field {
+                    DateField::Due => task.due_to,
+                    DateField::Created => task.created_at,
+                    DateField::Completed => task.completed_at,
+                };
+                date.is_some_and(|date| cmp.test(date))
+            }
+            Self::Id(cmp) => cmp.test(task.id),
+            Self::State(state) => task.state == *state,
+            Self::Ids(ids) => ids.contains(&task.id),
+            Self::Has(field) => match field {
+                HasField::Due => task.due_to.is_some(),
+                HasField::Rec => task.recurrence.is_some(),
+                HasField::Tag => !task.tags.is_empty(),
+                HasField::Project => !task.projects.is_empty(),
+                HasField::Context => !task.contexts.is_empty(),
+            },
+        }
+    }
+}
+
+fn invalid(message: String) -> Error {
+    Error::InvalidQuery(message)
+}
+
+/* 词法分析 */
+
+#[derive(PartialEq)]
+enum Token {
+    LParen,
+    RParen,
+    And,
+    Or,
+    Not,
+    Word(String),
+    /// 引号包裹的文本，总是按内容匹配
+    Quoted(String),
+}
+
+impl Token {
+    fn describe(&self) -> String {
+        match self {
+            Self::LParen => "'('".to_string(),
+            Self::RParen => "')'".to_string(),
+            Self::And => "'and'".to_string(),
+            Self::Or => "'or'".to_string(),
+            Self::Not => "'not'".to_string(),
+            Self::Word(w) | Self::Quoted(w) => format!("'{}'", w),
+        }
+    }
+}
+
+fn tokenize(s: &str) -> Result<Vec<Token>> {
+    let mut tokens = Vec::new();
+    let mut chars = s.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        match c {
+            c if c.is_whitespace() => _ = chars.next(),
+            '(' => {
+                chars.next();
+                tokens.push(Token::LParen);
+            }
+            ')' => {
+                chars.next();
+                tokens.push(Token::RParen);
+            }
+            '!' => {
+                chars.next();
+                tokens.push(Token::Not);
+            }
+            '"' | '\'' => {
+                chars.next();
+                let text: String = chars.by_ref().take_while(|ch| *ch != c).collect();
+                tokens.push(Token::Quoted(text));
+            }
+            _ => {
+                let mut word = String::new();
+                while let Some(&ch) = chars.peek() {
+                    if ch.is_whitespace() || ch == '(' || ch == ')' {
+                        break;
+                    }
+                    word.push(ch);
+                    chars.next();
+                }
+                tokens.push(match word.to_lowercase().as_str() {
+                    "and" | "&&" => Token::And,
+                    "or" | "||" => Token::Or,
+                    "not" => Token::Not,
+                    _ => Token::Word(word),
+                });
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+/* 语法分析 */
+
+struct QueryParser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl QueryParser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn parse_or(&mut self) -> Result<Query> {
+        let mut query = self.parse_and()?;
+        while self.peek() == Some(&Token::Or) {
+            self.pos += 1;
+            query = Query::Or(Box::new(query), Box::new(self.parse_and()?));
+        }
+        Ok(query)
+    }
+
+    /// 相邻的条件之间省略 and 时同样视为 and
+    fn parse_and(&mut self) -> Result<Query> {
+        let mut query = self.parse_not()?;
+        loop {
+            match self.peek() {
+                Some(Token::And) => self.pos += 1,
+                Some(Token::Or | Token::RParen) | None => break,
+                Some(_) => (),
+            }
+            query = Query::And(Box::new(query), Box::new(self.parse_not()?));
+        }
+        Ok(query)
+    }
+
+    fn parse_not(&mut self) -> Result<Query> {
+        if self.peek() == Some(&Token::Not) {
+            self.pos += 1;
+            return Ok(Query::Not(Box::new(self.parse_not()?)));
+        }
+        self.parse_atom()
+    }
+
+    fn parse_atom(&mut self) -> Result<Query> {
+        let token = self
+            .tokens
+            .get(self.pos)
+            .ok_or_else(|| invalid(t!("query.missing_condition").to_string()))?;
+        self.pos += 1;
+        match token {
+            Token::LParen => {
+                let query = self.parse_or()?;
+                if self.peek() != Some(&Token::RParen) {
+                    return Err(invalid(t!("query.missing_rparen").to_string()));
+                }
+                self.pos += 1;
+                Ok(query)
+            }
+            Token::Quoted(text) => Ok(Query::Text(text.clone())),
+            Token::Word(word) => parse_term(word),
+            token => Err(invalid(t!("query.unexpected", token.describe()))),
+        }
+    }
+}
+
+const OPERATORS: [&str; 7] = ["!=", "<=", ">=", ":", "=", "<", ">"];
+
+fn parse_term(word: &str) -> Result<Query> {
+    if let Some(tag) = word.strip_prefix('#').filter(|s| !s.is_empty()) {
+        return Ok(Query::Tag(tag.to_string()));
+    }
+    if let Some(project) = word.strip_prefix('+').filter(|s| !s.is_empty()) {
+        return Ok(Query::Project(project.to_string()));
+    }
+    if let Some(context) = word.strip_prefix('@').filter(|s| !s.is_empty()) {
+        return Ok(Query::Context(context.to_string()));
+    }
+
+    // 字段名由字母组成，其后是第一个出现的比较运算符
+    let split = OPERATORS
+        .iter()
+        .filter_map(|op| word.find(op).map(|i| (i, *op)))
+        .min_by_key(|(i, op)| (*i, std::cmp::Reverse(op.len())));
+    let Some((i, op)) = split.filter(|(i, _)| word[..*i].chars().all(char::is_alphabetic)) else {
+        return Ok(Query::Text(word.to_string()));
+    };
+    let (field, value) = (word[..i].to_lowercase(), &word[i + op.len()..]);
+    if value.is_empty() {
+        return Err(invalid(t!("query.missing_value", word)));
+    }
+
+    match field.as_str() {
+        "tag" => equal_only(op, word).map(|_| Query::Tag(value.to_string())),
+        "project" | "proj" => equal_only(op, word).map(|_| Query::Project(value.to_string())),
+        "context" | "ctx" => equal_only(op, word).map(|_| Query::Context(value.to_string())),
+        "text" | "content" => equal_only(op, word).map(|_| Query::Text(value.to_string())),
+        "pri" | "priority" => parse_cmp(op, value, |v| v.parse().ok()).map(Query::Priority),
+        "due" => parse_date_cmp(op, value).map(|cmp| Query::Date(DateField::Due, cmp)),
+        "created" => parse_date_cmp(op, value).map(|cmp| Query::Date(DateField::Created, cmp)),
+        "done" | "completed" => {
+            parse_date_cmp(op, value).map(|cmp| Query::Date(DateField::Completed, cmp))
+        }
+        "id" => parse_cmp(op, value, |v| v.parse().ok()).map(Query::Id),
+        "state" | "is" => {
+            equal_only(op, word)?;
+            let state = match value.to_lowercase().as_str() {
+                "p" | "pending" => State::Pending,
+                "i" | "in_progress" | "started" => State::InProgress,
+                "w" | "waiting" => State::Waiting,
+                "c" | "completed" | "done" => State::Completed,
+                "r" | "removed" => State::Removed,
+                _ => return Err(invalid(t!("query.unknown_state", value))),
+            };
+            Ok(Query::State(state))
+        }
+        "has" => {
+            equal_only(op, word)?;
+            let field = match value.to_lowercase().as_str() {
+                "due" => HasField::Due,
+                "rec" => HasField::Rec,
+                "tag" => HasField::Tag,
+                "project" | "proj" => HasField::Project,
+                "context" | "ctx" => HasField::Context,
+                _ => return Err(invalid(t!("query.unknown_field", value))),
+            };
+            Ok(Query::Has(field))
+        }
+        _ => Ok(Query::Text(word.to_string())),
+    }
+}
+
+fn equal_only(op: &str, word: &str) -> Result<()> {
+    match op {
+        ":" | "=" => Ok(()),
+        _ => Err(invalid(t!("query.invalid_operator", word))),
+    }
+}
+
+fn parse_date_cmp(op: &str, value: &str) -> Result<Cmp<Date>> {
+    parse_cmp(op, value, |v| v.parse().ok())
+}
+
+/// 解析比较运算与取值，`:` 后可以是 `from..to` 形式的范围
+fn parse_cmp<T>(op: &str, value: &str, parse: impl Fn(&str) -> Option<T>) -> Result<Cmp<T>> {
+    let parse = |v: &str| parse(v).ok_or_else(|| invalid(t!("query.invalid_value", v)));
+    if op == ":" {
+        if let Some((from, to)) = value.split_once("..") {
+            return Ok(Cmp::Range(parse(from)?, parse(to)?));
+        }
+    }
+    let v = parse(value)?;
+    Ok(match op {
+        ":" | "=" => Cmp::Eq(v),
+        "!=" => Cmp::Ne(v),
+        "<" => Cmp::Lt(v),
+        "<=" => Cmp::Le(v),
+        ">" => Cmp::Gt(v),
+        _ => Cmp::Ge(v),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::LineParser;
+
+    fn task(line: &str) -> Task {
+        LineParser::new().parse_line(line).unwrap()
+    }
+
+    fn matches(query: &str, line: &str) -> bool {
+        Query::parse(query).unwrap().matches(&task(line))
+    }
+
+    #[test]
+    fn empty_query_matches_everything() {
+        assert!(matches("", "[-] anything (2024-01-01)"));
+        assert!(matches("   ", "[-] anything (2024-01-01)"));
+    }
+
+    #[test]
+    fn and_binds_tighter_than_or() {
+        let line = "[A] alpha #rust (2024-01-01)";
+        assert!(matches("beta or alpha and #rust", line));
+        assert!(!matches("(beta or alpha) and #go", line));
+        assert!(matches("alpha #rust", line));
+        assert!(!matches("alpha and not #rust", line));
+        assert!(matches("!#go", line));
+    }
+
+    #[test]
+    fn quoted_text_is_not_a_keyword() {
+        assert!(matches("'or'", "[-] this or that (2024-01-01)"));
+        assert!(matches("\"pri:A\"", "[-] note pri:A (2024-01-01)"));
+    }
+
+    #[test]
+    fn priority_comparisons() {
+        assert!(matches("pri>=B", "[A] a (2024-01-01)"));
+        assert!(matches("pri>=B", "[B] b (2024-01-01)"));
+        assert!(!matches("pri>=B", "[C] c (2024-01-01)"));
+        assert!(!matches("pri>=B", "[-] none (2024-01-01)"));
+        assert!(matches("pri:O", "[o] oscar (2024-01-01)"));
+        assert!(!matches("pri>=Z", "[O] none (2024-01-01)"));
+        assert!(matches("pri<Z", "[-] none (2024-01-01)"));
+    }
+
+    #[test]
+    fn date_comparisons_and_ranges() {
+        let line = "[-] report (2024-01-01) (due:2024-03-15)";
+        assert!(matches("due<2024-04-01", line));
+        assert!(matches("due:2024-03-01..2024-03-31", line));
+        assert!(!matches("due:2024-04-01..2024-04-30", line));
+        assert!(matches("created=2024-01-01", line));
+        // 没有该日期的任务不匹配
+        assert!(!matches("done<2030-01-01", line));
+        assert!(!matches("created<2030-01-01", "[-] undated (-)"));
+    }
+
+    #[test]
+    fn fields_and_states() {
+        let line = "▶ [-] deploy +web @office (2024-01-01) (id:7)";
+        assert!(matches("+web @office", line));
+        assert!(matches("state:in_progress", line));
+        assert!(matches("id:5..9", line));
+        assert!(matches("has:project", line));
+        assert!(!matches("has:due", line));
+    }
+
+    #[test]
+    fn invalid_queries() {
+        for query in [
+            "(tag:a",
+            "tag:a)",
+            "tag:",
+            "and",
+            "not",
+            "state:unknown",
+            "has:color",
+            "tag<a",
+            "pri>=?",
+            "due<someday",
+        ] {
+            assert!(Query::parse(query).is_err(), "{}", query);
+        }
+    }
+}
@@ -0,0 +1,176 @@
+use std::fmt;
+use std::str::FromStr;
+
+use crate::date::Date;
+use crate::error::Error;
+
+/// 重复间隔的最大值，更大的间隔没有实际意义，且可能超出可表示的日期范围
+const MAX_AMOUNT: u32 = 999;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Unit {
+    Day,
+    Week,
+    Month,
+    Year,
+}
+
+/// 重复规则，如 `1w` 表示完成后一周再次到期，`+1m` 表示在原截止日期的基础上每月到期
+#[derive(Clone, Copy, PartialEq)]
+pub struct Recurrence {
+    /// 严格重复时以原截止日期为基准，否则以完成日期为基准
+    pub strict: bool,
+    pub amount: u32,
+    pub unit: Unit,
+}
+
+impl Recurrence {
+    /// 下一次任务的截止日期，超出可表示的日期范围时返回 None
+    pub fn next_due(&self, due_to: Option<Date>, completed_at: Date) -> Option<Date> {
+        let base = match due_to {
+            Some(due_to) if self.strict => due_to,
+            _ => completed_at,
+        };
+        let amount = i64::from(self.amount);
+        match self.unit {
+            Unit::Day => base.checked_add_days(amount),
+            Unit::Week => base.checked_add_days(amount * 7),
+            Unit::Month => base.checked_add_months(self.amount),
+            Unit::Year => base.checked_add_months(self.amount.checked_mul(12)?),
+        }
+    }
+}
+
+impl fmt::Display for Recurrence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let unit = match self.unit {
+            Unit::Day => 'd',
+            Unit::Week => 'w',
+            Unit::Month => 'm',
+            Unit::Year => 'y',
+        };
+        let strict = if self.strict { "+" } else { "" };
+        write!(f, "{}{}{}", strict, self.amount, unit)
+    }
+}
+
+impl FromStr for Recurrence {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || Error::InvalidRecurrence(s.to_string());
+
+        let (strict, rest) = match s.strip_prefix('+') {
+            Some(rest) => (true, rest),
+            None => (false, s),
+        };
+        let unit = match rest.chars().last().map(|c| c.to_ascii_lowercase()) {
+            Some('d') => Unit::Day,
+            Some('w') => Unit::Week,
+            Some('m') => Unit::Month,
+            Some('y') => Unit::Year,
+            _ => return Err(invalid()),
+        };
+        // 数量只能由数字组成，`u32` 的解析还会接受开头的 `+`
+        let amount = match &rest[..rest.len() - 1] {
+            "" => 1,
+            n if n.bytes().all(|b| b.is_ascii_digit()) => n.parse().map_err(|_| invalid())?,
+            _ => return Err(invalid()),
+        };
+        if amount == 0 || amount > MAX_AMOUNT {
+            return Err(invalid());
+        }
+
+        Ok(Self {
+            strict,
+            amount,
+            unit,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(s: &str) -> Date {
+        Date::parse_exact(s).unwrap()
+    }
+
+    fn next_due(rec: &str, due_to: Option<&str>, completed_at: &str) -> Option<String> {
+        let rec: Recurrence = rec.parse().unwrap();
+        rec.next_due(due_to.map(date), date(completed_at))
+            .map(|date| date.fmt())
+    }
+
+    #[test]
+    fn parse_and_display() {
+        for s in ["1d", "2w", "+1m", "+999y"] {
+            assert_eq!(s.parse::<Recurrence>().unwrap().to_string(), s);
+        }
+        assert_eq!("w".parse::<Recurrence>().unwrap().to_string(), "1w");
+        assert_eq!("+3M".parse::<Recurrence>().unwrap().to_string(), "+3m");
+    }
+
+    #[test]
+    fn invalid() {
+        for s in [
+            "",
+            "+",
+            "0d",
+            "1000d",
+            "-1d",
+            "1x",
+            "1.5d",
+            "++1d",
+            "99999999999d",
+        ] {
+            assert!(s.parse::<Recurrence>().is_err(), "{}", s);
+        }
+    }
+
+    #[test]
+    fn based_on_completion_or_due_date() {
+        assert_eq!(
+            next_due("1w", Some("2024-01-01"), "2024-01-10").as_deref(),
+            Some("2024-01-17")
+        );
+        assert_eq!(
+            next_due("+1w", Some("2024-01-01"), "2024-01-10").as_deref(),
+            Some("2024-01-08")
+        );
+        // 没有截止日期的严格重复以完成日期为基准
+        assert_eq!(
+            next_due("+3d", None, "2024-01-10").as_deref(),
+            Some("2024-01-13")
+        );
+    }
+
+    #[test]
+    fn months_are_clamped_to_the_last_day() {
+        assert_eq!(
+            next_due("+1m", Some("2024-01-31"), "2024-01-31").as_deref(),
+            Some("2024-02-29")
+        );
+        assert_eq!(
+            next_due("+1y", Some("2024-02-29"), "2024-02-29").as_deref(),
+            Some("2025-02-28")
+        );
+    }
+
+    #[test]
+    fn large_intervals_do_not_overflow() {
+        for rec in ["999d", "999w", "999m", "999y"] {
+            let rec: Recurrence = rec.parse().unwrap();
+            let mut due_to = date("9999-12-31");
+            // 不断重复，直到超出可表示的日期范围
+            let mut count = 0;
+            while let Some(next) = rec.next_due(None, due_to) {
+                assert!(next > due_to);
+                due_to = next;
+                count += 1;
+                assert!(count < 1_000_000);
+            }
+        }
+    }
+}
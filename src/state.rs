@@ -1,45 +1,64 @@
 use std::fmt;
-use std::io::Error;
 use std::str::FromStr;
 
-#[derive(PartialEq)]
+use crate::error::Error;
+
+/// 任务的状态
+#[derive(Clone, Default, PartialEq)]
 pub enum State {
+    #[default]
     Pending,
+    /// 进行中
+    InProgress,
+    /// 等待中，如等待他人答复
+    Waiting,
     Completed,
     Removed,
 }
 
-impl Default for State {
-    fn default() -> Self {
-        Self::Pending
-    }
-}
-
 impl State {
     pub fn as_str(&self) -> &str {
         match self {
             Self::Pending => "",
+            Self::InProgress => "▶ ",
+            Self::Waiting => "⏸ ",
             Self::Completed => "✓ ",
             Self::Removed => "✗ ",
         }
     }
+
+    /// 尚未完成也未移除，即未开始、进行中或等待中
+    pub fn is_open(&self) -> bool {
+        matches!(self, Self::Pending | Self::InProgress | Self::Waiting)
+    }
 }
 
-impl fmt::Display for State {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+impl State {
+    /// 用于机器可读输出的名称
+    pub fn name(&self) -> &str {
         match self {
-            Self::Pending => write!(f, ""),
-            Self::Completed => write!(f, "✓ "),
-            Self::Removed => write!(f, "✗ "),
+            Self::Pending => "pending",
+            Self::InProgress => "in_progress",
+            Self::Waiting => "waiting",
+            Self::Completed => "completed",
+            Self::Removed => "removed",
         }
     }
 }
 
+impl fmt::Display for State {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
 impl FromStr for State {
     type Err = Error;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         match s {
+            "▶" => Ok(Self::InProgress),
+            "⏸" => Ok(Self::Waiting),
             "✓" => Ok(Self::Completed),
             "✗" => Ok(Self::Removed),
             _ => Ok(Self::Pending),
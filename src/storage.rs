@@ -0,0 +1,108 @@
+use std::fs::{self, File, OpenOptions, TryLockError};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::error::{Error, Result};
+
+#[cfg(not(test))]
+const LOCK_TIMEOUT: Duration = Duration::from_secs(10);
+#[cfg(test)]
+const LOCK_TIMEOUT: Duration = Duration::from_millis(200);
+const LOCK_RETRY_INTERVAL: Duration = Duration::from_millis(50);
+
+/// 任务清单的排他锁，离开作用域时自动释放
+///
+/// 锁加在同目录下的 `.todo.txt.lock` 上，因为原子写入会替换任务清单本身
+pub struct FileLock {
+    _file: File,
+}
+
+/// 获取任务清单的排他锁，其他进程持有锁时最多等待 [`LOCK_TIMEOUT`]
+pub fn lock(file_path: &Path) -> Result<FileLock> {
+    let file_path = fs::canonicalize(file_path).unwrap_or_else(|_| file_path.to_path_buf());
+    let file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(sibling_path(&file_path, "lock"))?;
+
+    let start = Instant::now();
+    loop {
+        match file.try_lock() {
+            Ok(()) => return Ok(FileLock { _file: file }),
+            Err(TryLockError::WouldBlock) if start.elapsed() < LOCK_TIMEOUT => {
+                thread::sleep(LOCK_RETRY_INTERVAL)
+            }
+            Err(TryLockError::WouldBlock) => return Err(Error::Locked),
+            Err(TryLockError::Error(e)) => return Err(e.into()),
+        }
+    }
+}
+
+/// 原子地替换文件内容：先写入同目录下的临时文件并落盘，再重命名覆盖原文件，
+/// 写入过程中被中断时原文件保持不变；`backup` 为真时先将上一版本保留为 `.bak` 文件
+pub fn write_atomic(file_path: &Path, contents: &[u8], backup: bool) -> Result<()> {
+    // 任务清单是符号链接时替换其指向的文件，而不是链接本身
+    let file_path = fs::canonicalize(file_path).unwrap_or_else(|_| file_path.to_path_buf());
+    let tmp_path = sibling_path(&file_path, &format!("{}.tmp", process::id()));
+
+    let result = write_and_rename(&file_path, &tmp_path, contents, backup);
+    if result.is_err() {
+        _ = fs::remove_file(&tmp_path);
+    }
+    result
+}
+
+fn write_and_rename(
+    file_path: &Path,
+    tmp_path: &Path,
+    contents: &[u8],
+    backup: bool,
+) -> Result<()> {
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(tmp_path)?;
+    if let Ok(metadata) = fs::metadata(file_path) {
+        file.set_permissions(metadata.permissions())?;
+    }
+    file.write_all(contents)?;
+    file.sync_all()?;
+    drop(file);
+
+    if backup && file_path.exists() {
+        fs::copy(file_path, backup_path(file_path))?;
+    }
+    fs::rename(tmp_path, file_path)?;
+    sync_dir(file_path)?;
+    Ok(())
+}
+
+pub fn backup_path(file_path: &Path) -> PathBuf {
+    let mut name = file_path.file_name().unwrap_or_default().to_os_string();
+    name.push(".bak");
+    file_path.with_file_name(name)
+}
+
+/// 同目录下的隐藏辅助文件，如 `.todo.txt.tmp`
+fn sibling_path(file_path: &Path, suffix: &str) -> PathBuf {
+    let name = file_path.file_name().unwrap_or_default().to_string_lossy();
+    file_path.with_file_name(format!(".{}.{}", name, suffix))
+}
+
+#[cfg(unix)]
+fn sync_dir(file_path: &Path) -> Result<()> {
+    match file_path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        Some(dir) => File::open(dir)?.sync_all()?,
+        None => File::open(".")?.sync_all()?,
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn sync_dir(_file_path: &Path) -> Result<()> {
+    Ok(())
+}
@@ -0,0 +1,142 @@
+use crate::date::{self, Date};
+use crate::error::{Error, Result};
+use crate::parser;
+use crate::priority::Priority;
+use crate::recurrence::Recurrence;
+use crate::state::State;
+use crate::t;
+
+/// 一条任务
+#[derive(Clone)]
+pub struct Task {
+    /// 持久的任务编号，0 表示尚未分配
+    pub id: usize,
+    /// 在任务清单中的行号，从 1 开始，0 表示不是从文件中读取的
+    pub line: usize,
+    pub state: State,
+    pub priority: Priority,
+    pub content: String,
+    pub created_at: Option<Date>,
+    pub due_to: Option<Date>,
+    pub completed_at: Option<Date>,
+    pub recurrence: Option<Recurrence>,
+    /// 父任务的编号，子任务在列表中显示在父任务之下
+    pub parent: Option<usize>,
+    /// 前置任务的编号，见 [`crate::deps`]
+    pub deps: Vec<usize>,
+    /// iCalendar 中的 UID，只有从日历导入的任务才有
+    pub uid: Option<String>,
+    pub tags: Vec<String>,
+    pub projects: Vec<String>,
+    pub contexts: Vec<String>,
+}
+
+impl Task {
+    /// 今天创建的未开始任务，标签、项目和上下文从内容中解析
+    pub fn new(priority: Priority, content: String, due_to: Option<Date>) -> Self {
+        let mut task = Self {
+            id: 0,
+            line: 0,
+            state: State::Pending,
+            priority,
+            content: String::new(),
+            created_at: Some(date::today()),
+            due_to,
+            completed_at: None,
+            recurrence: None,
+            parent: None,
+            deps: Vec::new(),
+            uid: None,
+            tags: Vec::new(),
+            projects: Vec::new(),
+            contexts: Vec::new(),
+        };
+        task.set_labels(content);
+        task
+    }
+
+    /// 修改内容并重新解析标签、项目和上下文
+    pub fn set_content(&mut self, content: &str) -> Result<()> {
+        if content.is_empty() {
+            return Err(Error::InvalidInput(t!("error.empty_content").to_string()));
+        }
+        self.set_labels(content.to_string());
+        Ok(())
+    }
+
+    fn set_labels(&mut self, content: String) {
+        self.tags = parser::parse_tags(&content, &parser::tag_regex());
+        self.projects = parser::parse_tags(&content, &parser::project_regex());
+        self.contexts = parser::parse_tags(&content, &parser::context_regex());
+        self.content = content;
+    }
+
+    /// 将任务标记为今天完成，返回重复任务的下一次任务
+    pub fn complete(&mut self) -> Option<Task> {
+        self.state = State::Completed;
+        self.completed_at = Some(date::today());
+        self.next_recurrence()
+    }
+
+    /// 已完成的重复任务的下一次任务，编号在写入时分配；下一次的截止日期超出可表示的范围时没有下一次任务
+    pub fn next_recurrence(&self) -> Option<Task> {
+        let recurrence = self.recurrence?;
+        let completed_at = self.completed_at.unwrap_or_else(date::today);
+
+        let mut next = self.clone();
+        next.id = 0;
+        next.line = 0;
+        next.state = State::Pending;
+        next.created_at = Some(date::today());
+        next.completed_at = None;
+        next.uid = None;
+        next.due_to = Some(recurrence.next_due(self.due_to, completed_at)?);
+        Some(next)
+    }
+
+    /// 本程序格式的单行文本
+    pub fn stringify(&self) -> String {
+        // 没有创建日期（来自标准 todo.txt 格式）时写为 `(-)`
+        let created_at = self.created_at.map_or("-".to_string(), |date| date.fmt());
+        // `[O]` 表示没有优先级，优先级 O 写为 `[o]`
+        let priority = match self.priority {
+            Priority::O => "o",
+            _ => self.priority.as_str(),
+        };
+        let mut s = format!(
+            "{}[{}] {} ({})",
+            self.state, priority, self.content, created_at
+        );
+        if let Some(due_to) = self.due_to {
+            s.push_str(&format!(" (due:{})", due_to.fmt()));
+        }
+        if let Some(recurrence) = self.recurrence {
+            s.push_str(&format!(" (rec:{})", recurrence));
+        }
+        if let Some(parent) = self.parent {
+            s.push_str(&format!(" (parent:{})", parent));
+        }
+        if !self.deps.is_empty() {
+            s.push_str(&format!(" (dep:{})", join_ids(&self.deps)));
+        }
+        if let Some(uid) = &self.uid {
+            s.push_str(&format!(" (uid:{})", uid));
+        }
+        if self.id != 0 {
+            s.push_str(&format!(" (id:{})", self.id));
+        }
+        // 只有已完成的任务记录完成日期，与解析时的规则一致
+        if let Some(completed_at) = self.completed_at.filter(|_| self.state == State::Completed) {
+            s.push_str(&format!(" ({})", completed_at.fmt()));
+        }
+        s
+    }
+}
+
+/// 以逗号连接的任务编号，如 `3,5`
+pub(crate) fn join_ids(ids: &[usize]) -> String {
+    ids.iter()
+        .map(|id| id.to_string())
+        .collect::<Vec<_>>()
+        .join(",")
+}
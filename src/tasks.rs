@@ -1,338 +1,670 @@
-use colored::{Color, ColoredString, Colorize};
-use regex::Regex;
-use std::collections::HashMap;
-use std::fmt;
-use std::fs::OpenOptions;
-use std::io::{self, BufWriter, Error, ErrorKind, Result, Write};
-use std::path::PathBuf;
-
-use crate::cli::{DisplayMode, TaskConf};
-use crate::date::{self, Date};
-use crate::parser;
-use crate::priority::Priority;
-use crate::state::State;
-
-pub struct Task {
-    pub state: State,
-    pub priority: Priority,
-    pub content: String,
-    pub created_at: Date,
-    pub due_to: Option<Date>,
-    pub completed_at: Option<Date>,
-    pub tags: Vec<String>,
-}
-
-impl Task {
-    pub fn new(priority: Priority, content: String, due_to: Option<Date>) -> Self {
-        Self {
-            state: State::Pending,
-            priority,
-            content,
-            created_at: date::today(),
-            due_to,
-            completed_at: None,
-            tags: Vec::new(),
-        }
+use colored::Colorize;
+use std::cmp::Reverse;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use todotxt::{deps, ical, journal, t};
+use todotxt::{
+    Action, Change, Commit, Conflict, Date, Error, Priority, Query, Result, State, Task, TodoList,
+};
+
+use crate::cli::{DisplayMode, Target, TaskConf};
+use crate::config;
+use crate::output::{self, Format, Styled};
+
+pub fn match_mode(task: &Task, mode: &DisplayMode) -> bool {
+    match task.state {
+        State::Pending => mode.contains(DisplayMode::PENDING),
+        State::InProgress => mode.contains(DisplayMode::IN_PROGRESS),
+        State::Waiting => mode.contains(DisplayMode::WAITING),
+        State::Completed => mode.contains(DisplayMode::COMPLETED),
+        State::Removed => mode.contains(DisplayMode::REMOVED),
     }
 }
 
-impl Task {
-    fn fmt_due_to(&self) -> Option<ColoredString> {
-        let due_to = self.due_to.map(|date| date.fmt())?;
-        match self.state {
-            State::Completed => Some(due_to.color(
-                if self.completed_at.unwrap() > self.due_to.unwrap() {
-                    Color::Magenta
-                } else {
-                    Color::Green
-                },
-            )),
-            State::Removed => Some(due_to.dimmed()),
-            State::Pending => Some(due_to.color(if self.due_to.unwrap().is_over() {
-                Color::Red
-            } else {
-                Color::Cyan
-            })),
-        }
-    }
+/* 非交互式命令 */
 
-    fn fmt_completed_at(&self) -> Option<ColoredString> {
-        self.completed_at.map(|date| date.fmt().green())
+pub fn add_task(list: &mut TodoList, task: Task) -> Result<()> {
+    let linked = task.parent.iter().chain(&task.deps);
+    if let Some(&id) = linked.into_iter().find(|id| list.get(**id).is_none()) {
+        return Err(Error::UnknownId(id));
     }
+    list.add(task)?;
+    Ok(())
 }
 
-impl Task {
-    fn match_mode(&self, mode: &DisplayMode) -> bool {
-        match self.state {
-            State::Pending => mode.contains(DisplayMode::PENDING),
-            State::Completed => mode.contains(DisplayMode::COMPLETED),
-            State::Removed => mode.contains(DisplayMode::REMOVED),
-        }
+pub fn list_tasks(
+    list: &TodoList,
+    mode: &DisplayMode,
+    conf: &TaskConf,
+    format: Format,
+) -> Result<()> {
+    let query = conf.query(list.tasks())?;
+    // 显示已完成或已移除的任务时，一并列出归档中的任务，排在任务清单之前
+    let mut all = Vec::new();
+    if mode.intersects(DisplayMode::COMPLETED | DisplayMode::REMOVED) {
+        all = list.archived()?;
     }
+    if all.is_empty() {
+        all = get_tasks(list)?.to_vec();
+    } else {
+        all.extend_from_slice(list.tasks());
+    }
+    let tasks = filter_tasks(&all, mode, &query, conf.sort_by.as_deref());
 
-    fn match_conf(&self, conf: &TaskConf) -> bool {
-        self.contain_keyword(conf.keyword.as_deref())
-            && self.contain_tag(conf.tag.as_deref())
-            && self.higher_priority(conf.priority)
-            && self.before_due_to(conf.due_to)
+    let mut writer = BufWriter::new(io::stdout().lock());
+    if format == Format::Human {
+        output::write_tree(&mut writer, &tree(&tasks), &progress(list.tasks()))?;
+    } else {
+        output::write_tasks(&mut writer, &tasks, format)?;
     }
+    writer.flush()?;
+    Ok(())
+}
 
-    fn contain_keyword(&self, keyword: Option<&str>) -> bool {
-        match keyword {
-            Some(k) => self.content.contains(k),
-            None => true,
-        }
+/// 按显示模式和查询筛选任务，返回按显示顺序排列的任务
+pub fn filter_tasks<'a>(
+    tasks: &'a [Task],
+    mode: &DisplayMode,
+    query: &Query,
+    sort_by: Option<&str>,
+) -> Vec<&'a Task> {
+    let mut tasks = tasks
+        .iter()
+        .filter(|task| match_mode(task, mode) && query.matches(task))
+        .collect::<Vec<_>>();
+    sort_tasks(&mut tasks, sort_by, |task| task);
+
+    tasks.reverse();
+    tasks
+}
+
+/// 按排序方式排列任务，未指定时使用配置中的默认排序，`task` 取出每一项对应的任务
+fn sort_tasks<T>(items: &mut [T], sort_by: Option<&str>, task: impl Fn(&T) -> &Task) {
+    match sort_by.or(config::get().sort_by.as_deref()) {
+        Some("p" | "priority") => items.sort_by_key(|item| Reverse(task(item).priority)),
+        Some("d" | "due") => items.sort_by_key(|item| Reverse(task(item).due_to)),
+        _ => (),
     }
+}
 
-    fn contain_tag(&self, tag: Option<&str>) -> bool {
-        match tag {
-            Some(t) => self.tags.contains(&t.to_string()),
-            None => true,
+/// 将任务排列为树，子任务紧跟在父任务之后，返回 (深度, 任务)
+///
+/// 父任务不在其中的任务作为顶层任务，各层保持原来的相对顺序；归档中的任务没有编号，总是顶层任务
+pub fn tree<'a>(tasks: &[&'a Task]) -> Vec<(usize, &'a Task)> {
+    let index: HashMap<usize, usize> = tasks
+        .iter()
+        .enumerate()
+        .filter(|(_, task)| task.id != 0)
+        .map(|(i, task)| (task.id, i))
+        .collect();
+    let mut children = vec![Vec::new(); tasks.len()];
+    let mut roots = Vec::new();
+    for (i, task) in tasks.iter().enumerate() {
+        match task
+            .parent
+            .filter(|_| task.id != 0)
+            .and_then(|parent| index.get(&parent))
+        {
+            Some(&parent) if parent != i => children[parent].push(i),
+            _ => roots.push(i),
         }
     }
 
-    fn higher_priority(&self, priority: Option<Priority>) -> bool {
-        match priority {
-            Some(p) => self.priority >= p,
-            None => true,
+    let mut rows = Vec::with_capacity(tasks.len());
+    let mut visited = vec![false; tasks.len()];
+    // 父子关系成环时，环中的任务不会从顶层任务到达，依次以其中第一个任务作为顶层任务
+    for root in roots.into_iter().chain(0..tasks.len()) {
+        let mut stack = vec![(0, root)];
+        while let Some((depth, i)) = stack.pop() {
+            if visited[i] {
+                continue;
+            }
+            visited[i] = true;
+            rows.push((depth, tasks[i]));
+            stack.extend(children[i].iter().rev().map(|&child| (depth + 1, child)));
         }
     }
+    rows
+}
 
-    fn before_due_to(&self, due_to: Option<Date>) -> bool {
-        match due_to {
-            Some(d) if self.due_to.is_some() => self.due_to.unwrap() <= d,
-            Some(_) => false,
-            None => true,
+/// 各父任务的 (已完成, 全部) 子任务数，已移除的子任务不计
+pub fn progress(tasks: &[Task]) -> HashMap<usize, (usize, usize)> {
+    let mut progress: HashMap<usize, (usize, usize)> = HashMap::new();
+    for task in tasks.iter().filter(|task| task.state != State::Removed) {
+        if let Some(parent) = task.parent.filter(|parent| *parent != task.id) {
+            let (done, total) = progress.entry(parent).or_default();
+            *total += 1;
+            if task.state == State::Completed {
+                *done += 1;
+            }
         }
     }
+    progress
+}
 
-    fn stringify(&self) -> String {
-        let mut s = format!(
-            "{}[{}] {} ({})",
-            self.state,
-            self.priority,
-            self.content,
-            self.created_at.fmt()
-        );
-        if let Some(due_to) = self.due_to {
-            s.push_str(&format!(" (due:{})", due_to.fmt()));
-        }
-        if let Some(completed_at) = self.completed_at {
-            s.push_str(&format!(" ({})", completed_at.fmt()));
-        }
-        s
-    }
-}
-
-impl fmt::Display for Task {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let state = self.state.as_str();
-        let priority = self.priority.as_str();
-        let content = self.content.as_str();
-        let created_at = self.created_at.fmt();
-        if self.state == State::Removed {
-            write!(
-                f,
-                "{}",
-                format!("{state}[{priority}] {content} ({created_at})").dimmed()
-            )?;
-            if let Some(due_to) = self.fmt_due_to() {
-                write!(f, " {}", format!("(due:{due_to})").dimmed())?;
-            }
-        } else {
-            write!(
-                f,
-                "{}[{}] {} ({})",
-                state.green(),
-                priority.yellow(),
-                content,
-                created_at.blue()
-            )?;
-            if let Some(due_to) = self.fmt_due_to() {
-                write!(f, " (due:{due_to})")?;
-            }
-            if let Some(completed_at) = self.fmt_completed_at() {
-                write!(f, " ({completed_at})")?;
+/// 任务的所有后代在清单中的位置，按深度优先的顺序排列
+pub fn descendants(tasks: &[Task], id: usize) -> Vec<usize> {
+    let mut rows = Vec::new();
+    let mut visited = HashSet::from([id]);
+    let mut stack = vec![id];
+    while let Some(parent) = stack.pop() {
+        for (row, task) in tasks.iter().enumerate() {
+            if task.parent == Some(parent) && visited.insert(task.id) {
+                rows.push(row);
+                stack.push(task.id);
             }
         }
-        Ok(())
     }
+    rows
 }
 
-/* 非交互式命令 */
+pub fn list_projects(list: &TodoList, mode: &DisplayMode) -> Result<()> {
+    list_labels(list, mode, '+', |task| &task.projects)
+}
 
-pub fn add_task(file_path: &PathBuf, task: Task) -> Result<()> {
-    let mut file = OpenOptions::new()
-        .append(true)
-        .create(true)
-        .open(file_path)?;
-    writeln!(file, "{}", task.stringify())?;
-    Ok(())
+pub fn list_contexts(list: &TodoList, mode: &DisplayMode) -> Result<()> {
+    list_labels(list, mode, '@', |task| &task.contexts)
 }
 
-pub fn list_tasks(file_path: &PathBuf, mode: &DisplayMode, conf: &TaskConf) -> Result<()> {
-    let mut tasks = get_tasks(file_path)?
-        .into_iter()
-        .filter(|task| task.match_mode(mode) && task.match_conf(conf))
-        .collect::<Vec<_>>();
-    if let Some(sort_by) = &conf.sort_by {
-        match sort_by.as_str() {
-            "p" | "priority" => tasks.sort_by(|a, b| b.priority.cmp(&a.priority)),
-            "d" | "due" => tasks.sort_by(|a, b| b.due_to.cmp(&a.due_to)),
-            _ => (),
+/// 列出任务中出现的所有项目或上下文及其任务数，按任务数从多到少排列
+fn list_labels(
+    list: &TodoList,
+    mode: &DisplayMode,
+    prefix: char,
+    labels: fn(&Task) -> &Vec<String>,
+) -> Result<()> {
+    let mut counts: HashMap<&str, usize> = HashMap::new();
+    for task in get_tasks(list)?
+        .iter()
+        .filter(|task| match_mode(task, mode))
+    {
+        // 同一任务中重复出现的只计一次
+        let mut seen = HashSet::new();
+        for label in labels(task) {
+            if seen.insert(label) {
+                *counts.entry(label.as_str()).or_default() += 1;
+            }
         }
     }
 
-    let mut i = tasks.len() + 1;
+    let mut counts: Vec<(&str, usize)> = counts.into_iter().collect();
+    counts.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+
     let mut writer = BufWriter::new(io::stdout().lock());
-    for task in tasks.iter().rev() {
-        i -= 1;
-        writeln!(writer, "{:3} {}", i, task)?;
+    for (label, count) in counts {
+        writeln!(
+            writer,
+            "{:3} {}",
+            count,
+            format!("{}{}", prefix, label).yellow()
+        )?;
     }
     writer.flush()?;
     Ok(())
 }
 
-/* 交互式命令 */
+/* 交互式命令，指定任务编号时非交互式执行 */
+
+/// 完成任务，前置任务尚未完成时拒绝，指定 `force` 时只给出警告
+pub fn complete_tasks(list: &mut TodoList, mut target: Target, force: bool) -> Result<()> {
+    let tasks = get_tasks(list)?;
+
+    let selected = select_tasks(tasks, &mut target, 1)?;
+    let mut rows: Vec<usize> = selected.iter().map(|(_, row)| *row).collect();
+    for (id, _) in selected {
+        // 已选中的子任务不再计入
+        let pending: Vec<usize> = descendants(tasks, id)
+            .into_iter()
+            .filter(|child| tasks[*child].state.is_open() && !rows.contains(child))
+            .collect();
+        if !pending.is_empty() && confirm(&t!("tasks.complete_children", id, pending.len()))? {
+            rows.extend(pending);
+        }
+    }
 
-pub fn complete_tasks(file_path: &PathBuf, conf: &TaskConf) -> Result<()> {
-    let mut tasks = get_tasks(file_path)?;
+    // 同时完成的任务不再阻塞
+    let completing: HashSet<usize> = rows.iter().map(|row| tasks[*row].id).collect();
+    for row in &rows {
+        let task = &tasks[*row];
+        let deps: Vec<usize> = deps::unfinished(tasks, task)
+            .into_iter()
+            .filter(|dep| !completing.contains(dep))
+            .collect();
+        if deps.is_empty() {
+            continue;
+        }
+        if !force {
+            return Err(Error::Blocked { id: task.id, deps });
+        }
+        let deps: Vec<String> = deps.iter().map(|id| id.to_string()).collect();
+        eprintln!(
+            "{} {}",
+            "==>".yellow(),
+            t!("tasks.force_blocked", task.id, deps.join(", "))
+        );
+    }
 
-    let id2row = build_map(&tasks, conf)?;
-    prompt(1)?;
+    let mut changes = Vec::new();
+    for row in rows {
+        changes.extend(Change::complete(&tasks[row]));
+    }
+
+    commit_changes(list, Action::Done, changes)
+}
 
-    let selected_ids = get_input()?;
-    for id in selected_ids {
-        match id2row.get(&id) {
-            Some(row) => {
-                tasks[*row].state = State::Completed;
-                tasks[*row].completed_at = Some(date::today());
+pub fn modify_tasks(
+    list: &mut TodoList,
+    mut target: Target,
+    content: Option<String>,
+) -> Result<()> {
+    let tasks = get_tasks(list)?;
+
+    let mut changes = Vec::new();
+    if target.has_ids() {
+        // 指定任务编号时，优先级和截止日期是新的取值而非筛选条件
+        let priority = target
+            .conf
+            .priority
+            .take()
+            .map(|priority| config::get().check_priority(priority))
+            .transpose()?;
+        let due_to = target.conf.due_to.take();
+        if priority.is_none() && due_to.is_none() && content.is_none() {
+            return Err(Error::InvalidInput(t!("tasks.no_field").to_string()));
+        }
+        for (_, row) in select_tasks(tasks, &mut target, 2)? {
+            let mut task = tasks[row].clone();
+            if let Some(priority) = priority {
+                task.priority = priority;
+            }
+            if let Some(content) = &content {
+                task.set_content(content)?;
             }
-            None => eprintln!("{} 无效的任务编号: {}", "==>".red(), id),
+            if due_to.is_some() {
+                task.due_to = due_to;
+            }
+            changes.push(Change::update(&tasks[row], task));
+        }
+    } else {
+        for (id, row) in select_tasks(tasks, &mut target, 2)? {
+            println!("{} {}", "==>".cyan(), t!("tasks.which_field", id));
+            println!("{} {}", "==>".cyan(), t!("tasks.fields"));
+            prompt_input()?;
+
+            let mut task = tasks[row].clone();
+            edit_task(&mut task)?;
+            changes.push(Change::update(&tasks[row], task));
         }
     }
 
-    write_tasks(file_path, tasks)?;
-    Ok(())
+    commit_changes(list, Action::Modify, changes)
+}
+
+pub fn start_tasks(list: &mut TodoList, mut target: Target) -> Result<()> {
+    let tasks = get_tasks(list)?;
+
+    let changes = select_tasks(tasks, &mut target, 5)?
+        .into_iter()
+        .map(|(_, row)| {
+            let mut task = tasks[row].clone();
+            task.state = State::InProgress;
+            Change::update(&tasks[row], task)
+        })
+        .collect();
+
+    commit_changes(list, Action::Start, changes)
+}
+
+pub fn wait_tasks(list: &mut TodoList, mut target: Target) -> Result<()> {
+    let tasks = get_tasks(list)?;
+
+    let changes = select_tasks(tasks, &mut target, 6)?
+        .into_iter()
+        .map(|(_, row)| {
+            let mut task = tasks[row].clone();
+            task.state = State::Waiting;
+            Change::update(&tasks[row], task)
+        })
+        .collect();
+
+    commit_changes(list, Action::Wait, changes)
+}
+
+pub fn remove_tasks(list: &mut TodoList, mut target: Target) -> Result<()> {
+    let tasks = get_tasks(list)?;
+
+    let changes = select_tasks(tasks, &mut target, 3)?
+        .into_iter()
+        .map(|(_, row)| {
+            let mut task = tasks[row].clone();
+            task.state = State::Removed;
+            Change::update(&tasks[row], task)
+        })
+        .collect();
+
+    commit_changes(list, Action::Remove, changes)
 }
 
-pub fn modify_tasks(file_path: &PathBuf, conf: &TaskConf) -> Result<()> {
-    let mut tasks = get_tasks(file_path)?;
+pub fn delete_tasks(list: &mut TodoList, mut target: Target) -> Result<()> {
+    let tasks = get_tasks(list)?;
 
-    let id2row = build_map(&tasks, conf)?;
-    prompt(2)?;
+    let changes = select_tasks(tasks, &mut target, 4)?
+        .into_iter()
+        .map(|(_, row)| Change::delete(&tasks[row]))
+        .collect();
 
-    let selected_ids = get_input()?;
-    for id in selected_ids {
-        match id2row.get(&id) {
-            Some(row) => {
-                println!("{} 任务 {} 要修改的字段是?", "==>".cyan(), id);
-                println!(
-                    "{} 优先级 [P]riority, 内容 [C]ontent 或者 截止日期 [D]ue",
-                    "==>".cyan()
-                );
-                prompt_input()?;
+    commit_changes(list, Action::Delete, changes)
+}
 
-                edit_task(tasks.get_mut(*row).expect("获取任务失败"))?;
+/// 写入修改，并提示因其他进程的写入而放弃的修改
+pub fn commit_changes(list: &mut TodoList, action: Action, changes: Vec<Change>) -> Result<()> {
+    let Commit { conflicts, .. } = list.commit(action, changes)?;
+    for (id, conflict) in conflicts {
+        match conflict {
+            Conflict::Deleted => {
+                eprintln!("{} {}", "==>".red(), t!("tasks.deleted_elsewhere", id))
             }
-            None => eprintln!("{} 无效的任务编号: {}", "==>".red(), id),
+            _ => eprintln!("{} {}", "==>".red(), t!("tasks.modified_elsewhere", id)),
         }
     }
-    write_tasks(file_path, tasks)?;
+    Ok(())
+}
+
+/* 检查与修复 */
+
+pub fn check_tasks(list: &mut TodoList, fix: bool) -> Result<()> {
+    if list.invalid_lines().is_empty() {
+        println!("{} {}", "==>".green(), t!("tasks.no_problem"));
+        return Ok(());
+    }
 
+    for line in list.invalid_lines() {
+        println!(
+            "{} {}",
+            "==>".red(),
+            t!("tasks.line_error", line.line_no, line.column, line.reason)
+        );
+        println!("    {}", line.text.dimmed());
+    }
+    if !fix {
+        return Ok(());
+    }
+
+    let codec = list.codec();
+    for (line, task) in list.repair()? {
+        println!(
+            "{} {}",
+            "==>".green(),
+            t!("tasks.line_fixed", line.line_no, codec.stringify(&task))
+        );
+    }
     Ok(())
 }
 
-pub fn remove_tasks(file_path: &PathBuf, conf: &TaskConf) -> Result<()> {
-    let mut tasks = get_tasks(file_path)?;
+/* 撤销与重做 */
 
-    let id2row = build_map(&tasks, conf)?;
-    prompt(2)?;
+pub fn undo_tasks(list: &mut TodoList, count: usize) -> Result<()> {
+    for entry in list.undo(count)? {
+        println!(
+            "{} {}",
+            "==>".cyan(),
+            t!("tasks.undone", entry.seq, entry.action)
+        );
+    }
+    Ok(())
+}
 
-    let selected_ids = get_input()?;
-    for id in selected_ids {
-        match id2row.get(&id) {
-            Some(row) => tasks[*row].state = State::Removed,
-            None => eprintln!("{} 无效的任务编号: {}", "==>".red(), id),
-        }
+pub fn redo_tasks(list: &mut TodoList, count: usize) -> Result<()> {
+    for entry in list.redo(count)? {
+        println!(
+            "{} {}",
+            "==>".cyan(),
+            t!("tasks.redone", entry.seq, entry.action)
+        );
     }
+    Ok(())
+}
 
-    write_tasks(file_path, tasks)?;
+pub fn archive_tasks(
+    list: &mut TodoList,
+    state: &DisplayMode,
+    older_than: Option<Date>,
+) -> Result<()> {
+    if state.intersects(DisplayMode::OPEN) {
+        return Err(Error::InvalidInput(t!("tasks.archive_pending").to_string()));
+    }
+    let archived = list.archive(|task| {
+        // 已移除的任务没有记录移除日期，按创建日期计算；两者都没有时不按日期归档
+        let date = task.completed_at.or(task.created_at);
+        match_mode(task, state)
+            && older_than.is_none_or(|cutoff| date.is_some_and(|date| date <= cutoff))
+    })?;
+    if archived.is_empty() {
+        println!("{} {}", "==>".yellow(), t!("tasks.nothing_to_archive"));
+    } else {
+        println!(
+            "{} {}",
+            "==>".green(),
+            t!(
+                "tasks.archived",
+                archived.len(),
+                list.archive_path().display()
+            )
+        );
+    }
     Ok(())
 }
 
-pub fn delete_tasks(file_path: &PathBuf, conf: &TaskConf) -> Result<()> {
-    let mut tasks = get_tasks(file_path)?;
+/* 导入与导出 */
+
+pub fn export_tasks(
+    list: &TodoList,
+    mode: &DisplayMode,
+    conf: &TaskConf,
+    output: Option<PathBuf>,
+) -> Result<()> {
+    let query = conf.query(list.tasks())?;
+    let tasks = filter_tasks(list.tasks(), mode, &query, conf.sort_by.as_deref());
+    let calendar = ical::export(tasks);
+    match output {
+        Some(path) => fs::write(path, calendar)?,
+        None => io::stdout().write_all(calendar.as_bytes())?,
+    }
+    Ok(())
+}
 
-    let id2row = build_map(&tasks, conf)?;
-    prompt(3)?;
+/// 导入 iCalendar 文件中的任务，UID 与已有任务相同时更新该任务，内容相同时跳过
+pub fn import_tasks(list: &mut TodoList, path: &Path) -> Result<()> {
+    let imported = ical::import(&fs::read_to_string(path)?)?;
+    let existing: HashMap<String, &Task> = list
+        .tasks()
+        .iter()
+        .map(|task| (ical::uid(task), task))
+        .collect();
 
-    let selected_ids = get_input()?;
-    for id in selected_ids {
-        match id2row.get(&id) {
-            Some(row) => _ = tasks.remove(*row),
-            None => eprintln!("{} 无效的任务编号: {}", "==>".red(), id),
+    let mut changes = Vec::new();
+    let (mut added, mut updated, mut unchanged) = (0, 0, 0);
+    let mut seen = HashSet::new();
+    for task in imported {
+        // 同一个文件中 UID 重复的任务只导入第一个
+        if task
+            .uid
+            .as_ref()
+            .is_some_and(|uid| !seen.insert(uid.clone()))
+        {
+            continue;
+        }
+        let before = task.uid.as_ref().and_then(|uid| existing.get(uid));
+        match before {
+            Some(before) => {
+                // 创建日期、重复规则、编号和 UID 保持不变
+                let mut after = (*before).clone();
+                after.set_content(&task.content)?;
+                if ical::to_priority(before.priority) != ical::to_priority(task.priority) {
+                    after.priority = task.priority;
+                }
+                after.due_to = task.due_to;
+                // 日历中没有等待中的状态，等待中的任务导出后仍为 NEEDS-ACTION
+                if !(before.state == State::Waiting && task.state == State::Pending) {
+                    after.state = task.state;
+                }
+                after.completed_at = task.completed_at;
+                if after.stringify() == before.stringify() {
+                    unchanged += 1;
+                } else {
+                    changes.push(Change::update(before, after));
+                    updated += 1;
+                }
+            }
+            None => {
+                changes.push(Change::add(task));
+                added += 1;
+            }
         }
     }
-
-    write_tasks(file_path, tasks)?;
+    if !changes.is_empty() {
+        commit_changes(list, Action::Import, changes)?;
+    }
+    println!(
+        "{} {}",
+        "==>".green(),
+        t!("tasks.imported", added, updated, unchanged)
+    );
     Ok(())
 }
 
-/* 功能函数 */
+pub fn show_history(list: &TodoList, limit: usize) -> Result<()> {
+    let entries = list.history()?;
+    let (undo_stack, _) = journal::stacks(&entries);
 
-fn get_tasks(file_path: &PathBuf) -> Result<Vec<Task>> {
-    parser::parse_file(file_path).and_then(|tasks| {
-        if tasks.is_empty() {
-            Err(Error::new(ErrorKind::InvalidInput, "任务清单为空"))
+    let mut writer = BufWriter::new(io::stdout().lock());
+    for entry in entries.iter().rev().take(limit) {
+        let header = format!(
+            "{} {} {}",
+            format!("#{}", entry.seq).yellow(),
+            entry.time.to_string().blue(),
+            entry.action
+        );
+        let undone = !entry.changes.is_empty() && !undo_stack.contains(&entry.seq);
+        if undone {
+            writeln!(writer, "{} {}", header, t!("tasks.undone_mark").dimmed())?;
         } else {
-            Ok(tasks)
+            writeln!(writer, "{}", header)?;
+        }
+        for change in &entry.changes {
+            if let Some(before) = &change.before {
+                writeln!(writer, "  {} {:3} {}", "-".red(), before.id, Styled(before))?;
+            }
+            if let Some(after) = &change.after {
+                writeln!(writer, "  {} {:3} {}", "+".green(), after.id, Styled(after))?;
+            }
         }
-    })
+    }
+    writer.flush()?;
+    Ok(())
 }
 
-fn build_map(tasks: &[Task], conf: &TaskConf) -> Result<HashMap<usize, usize>> {
+/* 功能函数 */
+
+pub fn get_tasks(list: &TodoList) -> Result<&[Task]> {
+    let invalid = list.invalid_lines().len();
+    if invalid > 0 {
+        eprintln!("{} {}", "==>".yellow(), t!("tasks.invalid_lines", invalid));
+    }
+    if list.tasks().is_empty() {
+        Err(Error::EmptyList)
+    } else {
+        Ok(list.tasks())
+    }
+}
+
+/// 按照筛选和排序规则选出未完成的任务，返回按显示顺序排列的 (编号, 行号)
+fn index_tasks(tasks: &[Task], conf: &TaskConf) -> Result<Vec<(usize, usize)>> {
+    let query = conf.query(tasks)?;
     let mut sorted_tasks: Vec<(usize, &Task)> = tasks
         .iter()
         .enumerate()
-        .filter(|(_, task)| task.state == State::Pending && task.match_conf(conf))
+        .filter(|(_, task)| task.state.is_open() && query.matches(task))
         .collect();
-    if let Some(sort_by) = &conf.sort_by {
-        match sort_by.as_str() {
-            "p" | "priority" => sorted_tasks.sort_by(|a, b| b.1.priority.cmp(&a.1.priority)),
-            "d" | "due" => sorted_tasks.sort_by(|a, b| b.1.due_to.cmp(&a.1.due_to)),
-            _ => (),
-        }
-    }
+    sort_tasks(&mut sorted_tasks, conf.sort_by.as_deref(), |(_, task)| task);
+
+    Ok(sorted_tasks
+        .into_iter()
+        .rev()
+        .map(|(row, task)| (task.id, row))
+        .collect())
+}
+
+fn build_map(tasks: &[Task], conf: &TaskConf) -> Result<HashMap<usize, usize>> {
+    let indexed = index_tasks(tasks, conf)?;
 
-    let mut id = sorted_tasks.len() + 1;
-    let mut map = HashMap::new();
     let mut writer = BufWriter::new(io::stdout().lock());
-    for (row, task) in sorted_tasks.iter().rev() {
-        id -= 1;
-        map.insert(id, *row);
-        writeln!(writer, "{:3} {}", id, task)?;
+    for (id, row) in &indexed {
+        writeln!(writer, "{:3} {}", id, Styled(&tasks[*row]))?;
     }
     writer.flush()?;
-    Ok(map)
+    Ok(indexed.into_iter().collect())
+}
+
+/// 选择要操作的任务：未指定任务编号时列出任务并交互式输入
+fn select_tasks(tasks: &[Task], target: &mut Target, action: u8) -> Result<Vec<(usize, usize)>> {
+    let ids = target.take_ids();
+    if ids.is_empty() {
+        let id2row = build_map(tasks, &target.conf)?;
+        prompt(action)?;
+
+        let selected = get_input()?
+            .into_iter()
+            .filter_map(|id| match id2row.get(&id) {
+                Some(row) => Some((id, *row)),
+                None => {
+                    eprintln!("{} {}", "==>".red(), Error::UnknownId(id));
+                    None
+                }
+            })
+            .collect();
+        Ok(selected)
+    } else {
+        let id2row: HashMap<usize, usize> = index_tasks(tasks, &target.conf)?.into_iter().collect();
+        ids.into_iter()
+            .map(|id| match id2row.get(&id) {
+                Some(row) => Ok((id, *row)),
+                None => Err(Error::UnknownId(id)),
+            })
+            .collect()
+    }
+}
+
+/// 询问是否继续，直接回车为是，没有输入（如标准输入已关闭）时为否
+fn confirm(message: &str) -> Result<bool> {
+    println!("{} {}", "==>".cyan(), message);
+    prompt_input()?;
+    let mut input = String::new();
+    if io::stdin().read_line(&mut input)? == 0 {
+        return Ok(false);
+    }
+    Ok(!input.trim().eq_ignore_ascii_case("n"))
 }
 
 fn prompt(action: u8) -> Result<()> {
-    print!("{} ", "==>".cyan());
-    match action {
-        1 => print!("要完成的任务"),
-        2 => print!("要修改的任务"),
-        3 => print!("要移除的任务"),
-        4 => print!("要删除的任务"),
-        _ => return Err(Error::new(ErrorKind::InvalidInput, "无效的操作")),
-    }
-    println!(": (示例: 1 3 4)");
+    let message = match action {
+        1 => t!("tasks.select_done"),
+        2 => t!("tasks.select_modify"),
+        3 => t!("tasks.select_remove"),
+        4 => t!("tasks.select_delete"),
+        5 => t!("tasks.select_start"),
+        6 => t!("tasks.select_wait"),
+        _ => return Err(Error::InvalidInput(t!("tasks.invalid_action").to_string())),
+    };
+    println!("{} {}{}", "==>".cyan(), message, t!("tasks.select_example"));
     prompt_input()?;
 
     Ok(())
 }
 
-fn prompt_input() -> Result<()> {
+pub fn prompt_input() -> Result<()> {
     print!("{} ", "==>".green());
     io::stdout().flush()?;
     Ok(())
@@ -346,7 +678,7 @@ fn get_input() -> Result<Vec<usize>> {
         .filter_map(|s| s.parse().ok())
         .collect();
     if ids.is_empty() {
-        eprintln!("{} 未输入任务编号", "==>".red());
+        eprintln!("{} {}", "==>".red(), t!("tasks.no_ids"));
     }
 
     Ok(ids)
@@ -358,54 +690,100 @@ fn edit_task(task: &mut Task) -> Result<()> {
     let field = input.trim();
     match field {
         "P" | "p" => {
-            println!("{} 优先级: (A/B/C/O)", "==>".cyan());
+            let conf = config::get();
+            println!(
+                "{} {}",
+                "==>".cyan(),
+                t!("tasks.priority_prompt", conf.priority_range())
+            );
             prompt_input()?;
             let mut input = String::new();
             io::stdin().read_line(&mut input)?;
-            task.priority = input.trim().chars().next().unwrap_or('O').into();
+            task.priority = match input.trim() {
+                "" => Priority::default(),
+                s => conf.check_priority(s.parse()?)?,
+            };
         }
         "C" | "c" => {
-            println!("{} 内容:", "==>".cyan());
+            println!("{} {}", "==>".cyan(), t!("tasks.content_prompt"));
             prompt_input()?;
             let mut input = String::new();
             io::stdin().read_line(&mut input)?;
             if input.trim().is_empty() {
-                eprintln!("{} 未输入内容", "==>".red());
+                eprintln!("{} {}", "==>".red(), t!("error.empty_content"));
             } else {
-                task.content = input.trim().to_string();
-                let re_tag = Regex::new(r"(?:\s|^)#(\w+)(?:\s|$)").unwrap();
-                task.tags = parser::parse_tags(&task.content, &re_tag);
+                task.set_content(input.trim())?;
             }
         }
         "D" | "d" => {
-            println!("{} 截止日期: (YYYY-MM-DD)", "==>".cyan());
+            println!("{} {}", "==>".cyan(), t!("tasks.due_prompt"));
             prompt_input()?;
             let mut input = String::new();
             io::stdin().read_line(&mut input)?;
             if input.trim().is_empty() {
                 task.due_to = None;
             } else {
-                // task.due_to = Some(date::get_date(input.trim()));
-                task.due_to = Some(input.trim().parse().unwrap());
+                task.due_to = Some(input.trim().parse()?);
             }
         }
-        _ => eprintln!("{} 无效的字段: {}", "==>".red(), field),
+        _ => eprintln!("{} {}", "==>".red(), t!("tasks.invalid_field", field)),
     }
 
     Ok(())
 }
 
-fn write_tasks(file_path: &PathBuf, tasks: Vec<Task>) -> Result<()> {
-    let file = OpenOptions::new()
-        .write(true)
-        .truncate(true)
-        .open(file_path)?;
-    let mut writer = BufWriter::new(file);
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use todotxt::parser::LineParser;
+
+    fn parse(lines: &[&str]) -> Vec<Task> {
+        let parser = LineParser::new();
+        lines
+            .iter()
+            .map(|line| parser.parse_line(line).unwrap())
+            .collect()
+    }
 
-    for task in tasks {
-        writeln!(writer, "{}", task.stringify())?;
+    #[test]
+    fn tree_puts_children_after_parents() {
+        let tasks = parse(&[
+            "[-] child of 3 (-) (parent:3) (id:1)",
+            "[-] orphan (-) (parent:9) (id:2)",
+            "[-] root (-) (id:3)",
+            "[-] grandchild (-) (parent:1) (id:4)",
+            "[-] second child (-) (parent:3) (id:5)",
+            // 父子关系成环的任务也会出现
+            "[-] loop a (-) (parent:7) (id:6)",
+            "[-] loop b (-) (parent:6) (id:7)",
+        ]);
+        let refs: Vec<&Task> = tasks.iter().collect();
+        let rows: Vec<(usize, usize)> = tree(&refs)
+            .into_iter()
+            .map(|(depth, task)| (depth, task.id))
+            .collect();
+        assert_eq!(
+            rows,
+            vec![(0, 2), (0, 3), (1, 1), (2, 4), (1, 5), (0, 6), (1, 7)]
+        );
     }
-    writer.flush()?;
 
-    Ok(())
+    #[test]
+    fn progress_counts_children() {
+        let tasks = parse(&[
+            "[-] parent (-) (id:1)",
+            "✓ [-] done (-) (parent:1) (id:2) (2024-01-01)",
+            "[-] open (-) (parent:1) (id:3)",
+            "✗ [-] removed (-) (parent:1) (id:4)",
+            "[-] grandchild (-) (parent:3) (id:5)",
+            "[-] self (-) (parent:6) (id:6)",
+        ]);
+        let progress = progress(&tasks);
+        assert_eq!(progress.get(&1), Some(&(1, 2)));
+        assert_eq!(progress.get(&3), Some(&(0, 1)));
+        assert_eq!(progress.len(), 2);
+
+        let rows = descendants(&tasks, 1);
+        assert_eq!(rows, vec![1, 2, 3, 4]);
+    }
 }
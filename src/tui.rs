@@ -0,0 +1,695 @@
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Constraint, Layout, Position};
+use ratatui::style::{Color, Style, Stylize};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, Borders, List, ListItem, ListState, Paragraph, Tabs};
+use ratatui::{DefaultTerminal, Frame};
+use std::iter;
+use todotxt::{
+    deps, t, Action, Change, Date, Error, Priority, Query, Result, State, Task, TodoList,
+};
+use unicode_width::UnicodeWidthStr;
+
+use crate::cli::{DisplayMode, TaskConf};
+use crate::config::{self, Theme};
+use crate::output;
+use crate::tasks;
+
+/// 标签页及其显示模式
+fn tabs() -> [(&'static str, DisplayMode); 6] {
+    [
+        (t!("tui.tab_open"), DisplayMode::OPEN),
+        (t!("tui.tab_in_progress"), DisplayMode::IN_PROGRESS),
+        (t!("tui.tab_waiting"), DisplayMode::WAITING),
+        (t!("tui.tab_completed"), DisplayMode::COMPLETED),
+        (t!("tui.tab_removed"), DisplayMode::REMOVED),
+        (t!("tui.tab_all"), DisplayMode::all()),
+    ]
+}
+
+/// 底部输入框正在编辑的内容
+#[derive(Clone, Copy, PartialEq)]
+enum Editing {
+    Filter,
+    Content,
+    Due,
+    Priority,
+    New,
+}
+
+impl Editing {
+    fn label(&self) -> &str {
+        match self {
+            Self::Filter => t!("tui.filter"),
+            Self::Content => t!("tui.content"),
+            Self::Due => t!("tui.due"),
+            Self::Priority => t!("tui.priority"),
+            Self::New => t!("tui.new"),
+        }
+    }
+}
+
+struct Input {
+    editing: Editing,
+    text: String,
+    /// 光标位置，按字符计数
+    cursor: usize,
+}
+
+impl Input {
+    fn new(editing: Editing, text: String) -> Self {
+        let cursor = text.chars().count();
+        Self {
+            editing,
+            text,
+            cursor,
+        }
+    }
+
+    fn byte_index(&self) -> usize {
+        self.text
+            .char_indices()
+            .nth(self.cursor)
+            .map_or(self.text.len(), |(i, _)| i)
+    }
+
+    /// 处理编辑按键，返回内容是否改变
+    fn handle(&mut self, key: KeyEvent) -> bool {
+        match key.code {
+            KeyCode::Char('u') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                self.text.clear();
+                self.cursor = 0;
+                true
+            }
+            KeyCode::Char(_) if key.modifiers.contains(KeyModifiers::CONTROL) => false,
+            KeyCode::Char(c) => {
+                let i = self.byte_index();
+                self.text.insert(i, c);
+                self.cursor += 1;
+                true
+            }
+            KeyCode::Backspace if self.cursor > 0 => {
+                self.cursor -= 1;
+                let i = self.byte_index();
+                self.text.remove(i);
+                true
+            }
+            KeyCode::Delete if self.cursor < self.text.chars().count() => {
+                let i = self.byte_index();
+                self.text.remove(i);
+                true
+            }
+            KeyCode::Left => {
+                self.cursor = self.cursor.saturating_sub(1);
+                false
+            }
+            KeyCode::Right => {
+                self.cursor = (self.cursor + 1).min(self.text.chars().count());
+                false
+            }
+            KeyCode::Home => {
+                self.cursor = 0;
+                false
+            }
+            KeyCode::End => {
+                self.cursor = self.text.chars().count();
+                false
+            }
+            _ => false,
+        }
+    }
+}
+
+struct App<'a> {
+    list: &'a mut TodoList,
+    conf: TaskConf,
+    tab: usize,
+    /// 交互式输入的查询语句，与命令行的筛选参数组合
+    filter: String,
+    query: Query,
+    /// 当前显示的任务编号，按显示顺序排列
+    ids: Vec<usize>,
+    state: ListState,
+    input: Option<Input>,
+    /// 等待确认是否一并完成子任务的任务编号，及其未完成的子任务的编号
+    confirm: Option<(usize, Vec<usize>)>,
+    /// 状态栏中的消息，第二项表示是否为错误
+    message: Option<(String, bool)>,
+    quit: bool,
+}
+
+pub fn run(list: &mut TodoList, conf: TaskConf, mode: DisplayMode) -> Result<()> {
+    let query = conf.query(list.tasks())?;
+    let tab = tabs().iter().position(|(_, m)| *m == mode).unwrap_or(0);
+    let mut app = App {
+        list,
+        conf,
+        tab,
+        filter: String::new(),
+        query,
+        ids: Vec::new(),
+        state: ListState::default().with_selected(Some(0)),
+        input: None,
+        confirm: None,
+        message: None,
+        quit: false,
+    };
+    app.refresh();
+
+    let mut terminal = ratatui::try_init()?;
+    let result = app.run(&mut terminal);
+    ratatui::restore();
+    result
+}
+
+impl App<'_> {
+    fn run(&mut self, terminal: &mut DefaultTerminal) -> Result<()> {
+        while !self.quit {
+            terminal.draw(|frame| self.draw(frame))?;
+            if let Event::Key(key) = event::read()? {
+                if key.kind == KeyEventKind::Press {
+                    self.handle_key(key);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// 重新筛选任务，尽量保持选中的任务不变
+    fn refresh(&mut self) {
+        let selected = self.selected().map(|task| task.id);
+        let mode = tabs()[self.tab].1;
+        self.ids = tasks::filter_tasks(
+            self.list.tasks(),
+            &mode,
+            &self.query,
+            self.conf.sort_by.as_deref(),
+        )
+        .iter()
+        .map(|task| task.id)
+        .collect();
+
+        let row = selected
+            .and_then(|id| self.ids.iter().position(|i| *i == id))
+            .unwrap_or(self.row());
+        self.select(row);
+    }
+
+    fn row(&self) -> usize {
+        self.state.selected().unwrap_or(0)
+    }
+
+    /// 选中某一行，超出范围时选中最后一行
+    fn select(&mut self, row: usize) {
+        self.state
+            .select(Some(row.min(self.ids.len().saturating_sub(1))));
+    }
+
+    fn selected(&self) -> Option<&Task> {
+        let id = self.ids.get(self.state.selected()?)?;
+        self.list.get(*id)
+    }
+
+    fn info(&mut self, message: String) {
+        self.message = Some((message, false));
+    }
+
+    fn error(&mut self, message: String) {
+        self.message = Some((message, true));
+    }
+
+    /* 按键处理 */
+
+    fn handle_key(&mut self, key: KeyEvent) {
+        if self.input.is_some() {
+            self.handle_input(key);
+            return;
+        }
+        if self.confirm.is_some() {
+            self.handle_confirm(key);
+            return;
+        }
+
+        self.message = None;
+        match key.code {
+            KeyCode::Char('q') | KeyCode::Esc => self.quit = true,
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => self.quit = true,
+            KeyCode::Char('j') | KeyCode::Down => self.select(self.row() + 1),
+            KeyCode::Char('k') | KeyCode::Up => self.select(self.row().saturating_sub(1)),
+            KeyCode::Char('g') | KeyCode::Home => self.select(0),
+            KeyCode::Char('G') | KeyCode::End => self.select(usize::MAX),
+            KeyCode::Tab | KeyCode::Char('l') | KeyCode::Right => self.switch_tab(self.tab + 1),
+            KeyCode::BackTab | KeyCode::Char('h') | KeyCode::Left => {
+                self.switch_tab(self.tab + tabs().len() - 1)
+            }
+            KeyCode::Char(c @ '1'..='6') => self.switch_tab(c as usize - '1' as usize),
+            KeyCode::Char('x') | KeyCode::Char(' ') => self.toggle_done(),
+            KeyCode::Char('r') => self.toggle_removed(),
+            KeyCode::Char('s') => self.toggle_state(State::InProgress, Action::Start),
+            KeyCode::Char('w') => self.toggle_state(State::Waiting, Action::Wait),
+            // 在已启用的优先级和没有优先级之间循环
+            KeyCode::Char('p') => self.set_priority(|priority| match priority.level() {
+                Some(level) if level < config::get().priority_levels => {
+                    Priority::levels(level + 1).last().unwrap()
+                }
+                Some(_) => Priority::NONE,
+                None => Priority::A,
+            }),
+            // 输入任意已启用的优先级，留空表示没有优先级
+            KeyCode::Char('P') => {
+                if let Some(task) = self.selected() {
+                    let priority = match task.priority.level() {
+                        Some(_) => task.priority.to_string(),
+                        None => String::new(),
+                    };
+                    self.input = Some(Input::new(Editing::Priority, priority));
+                }
+            }
+            KeyCode::Char('e') => {
+                if let Some(content) = self.selected().map(|task| task.content.clone()) {
+                    self.input = Some(Input::new(Editing::Content, content));
+                }
+            }
+            KeyCode::Char('d') => {
+                if let Some(task) = self.selected() {
+                    let due_to = task.due_to.map(|date| date.fmt()).unwrap_or_default();
+                    self.input = Some(Input::new(Editing::Due, due_to));
+                }
+            }
+            KeyCode::Char('n') => self.input = Some(Input::new(Editing::New, String::new())),
+            KeyCode::Char('/') => {
+                self.input = Some(Input::new(Editing::Filter, self.filter.clone()))
+            }
+            KeyCode::Char('u') => self.replay(true),
+            KeyCode::Char('U') => self.replay(false),
+            // 未被其他按键占用的大写字母直接设置优先级，`-` 清除优先级
+            KeyCode::Char(c) if c.is_ascii_uppercase() || c == '-' => {
+                match Priority::try_from(c).and_then(|p| config::get().check_priority(p)) {
+                    Ok(priority) => self.set_priority(|_| priority),
+                    Err(e) => self.error(e.to_string()),
+                }
+            }
+            _ => (),
+        }
+    }
+
+    fn handle_input(&mut self, key: KeyEvent) {
+        let input = self.input.as_mut().unwrap();
+        match key.code {
+            KeyCode::Esc => {
+                // 取消筛选时恢复原来的筛选条件
+                if input.editing == Editing::Filter {
+                    let filter = self.filter.clone();
+                    self.apply_filter(&filter);
+                }
+                self.input = None;
+            }
+            KeyCode::Enter => {
+                let input = self.input.take().unwrap();
+                if let Err(e) = self.submit(&input) {
+                    self.error(e.to_string());
+                    self.input = Some(input);
+                }
+            }
+            _ => {
+                // 筛选条件随输入实时生效
+                if input.handle(key) && input.editing == Editing::Filter {
+                    let text = input.text.clone();
+                    self.apply_filter(&text);
+                }
+            }
+        }
+    }
+
+    /// 与 done 命令一致，回车或 `y` 一并完成子任务，`n` 只完成该任务，Esc 取消
+    fn handle_confirm(&mut self, key: KeyEvent) {
+        let (id, children) = self.confirm.take().unwrap();
+        match key.code {
+            KeyCode::Enter | KeyCode::Char('y') | KeyCode::Char('Y') => {
+                self.complete(iter::once(id).chain(children).collect())
+            }
+            KeyCode::Char('n') | KeyCode::Char('N') => self.complete(vec![id]),
+            KeyCode::Esc => (),
+            _ => self.confirm = Some((id, children)),
+        }
+    }
+
+    fn apply_filter(&mut self, filter: &str) {
+        match Query::parse(filter)
+            .and_then(|query| Ok(self.conf.query(self.list.tasks())?.and(query)))
+        {
+            Ok(query) => {
+                self.query = query;
+                self.message = None;
+                self.refresh();
+            }
+            Err(e) => self.error(e.to_string()),
+        }
+    }
+
+    fn submit(&mut self, input: &Input) -> Result<()> {
+        let text = input.text.trim();
+        match input.editing {
+            Editing::Filter => {
+                Query::parse(text)?;
+                self.filter = text.to_string();
+                self.apply_filter(text);
+            }
+            Editing::Content => self.update(Action::Modify, |task| task.set_content(text))?,
+            Editing::Due => {
+                let due_to = match text {
+                    "" => None,
+                    s => Some(s.parse::<Date>()?),
+                };
+                self.update(Action::Modify, |task| {
+                    task.due_to = due_to;
+                    Ok(())
+                })?;
+            }
+            Editing::Priority => {
+                let priority = match text {
+                    "" => Priority::NONE,
+                    s => config::get().check_priority(s.parse()?)?,
+                };
+                self.set_priority(|_| priority);
+            }
+            Editing::New => {
+                if text.is_empty() {
+                    return Err(Error::InvalidInput(t!("error.empty_content").to_string()));
+                }
+                let defaults = config::get();
+                let task = Task::new(defaults.priority, text.to_string(), defaults.due_to);
+                let id = self.list.add(task)?;
+                self.info(t!("tui.added", id));
+                self.refresh();
+            }
+        }
+        Ok(())
+    }
+
+    /* 修改任务 */
+
+    fn switch_tab(&mut self, tab: usize) {
+        self.tab = tab % tabs().len();
+        self.state.select(None);
+        self.refresh();
+    }
+
+    fn toggle_done(&mut self) {
+        let Some(task) = self.selected() else {
+            return;
+        };
+        if task.state == State::Completed {
+            let mut after = task.clone();
+            after.state = State::Pending;
+            after.completed_at = None;
+            let change = Change::update(task, after);
+            self.commit(Action::Modify, vec![change]);
+        } else {
+            // 与 done 命令一致，有未完成的子任务时询问是否一并完成
+            let tasks = self.list.tasks();
+            let children: Vec<usize> = tasks::descendants(tasks, task.id)
+                .into_iter()
+                .filter(|row| tasks[*row].state.is_open())
+                .map(|row| tasks[row].id)
+                .collect();
+            if children.is_empty() {
+                self.complete(vec![task.id]);
+            } else {
+                self.confirm = Some((task.id, children));
+            }
+        }
+    }
+
+    /// 完成任务，前置任务未完成时拒绝完成，同时完成的任务不再阻塞
+    fn complete(&mut self, ids: Vec<usize>) {
+        let tasks = self.list.tasks();
+        let mut changes = Vec::new();
+        for task in ids.iter().filter_map(|id| self.list.get(*id)) {
+            let deps: Vec<usize> = deps::unfinished(tasks, task)
+                .into_iter()
+                .filter(|dep| !ids.contains(dep))
+                .collect();
+            if !deps.is_empty() {
+                let error = Error::Blocked { id: task.id, deps };
+                self.error(error.to_string());
+                return;
+            }
+            changes.extend(Change::complete(task));
+        }
+        self.commit(Action::Done, changes);
+    }
+
+    fn toggle_removed(&mut self) {
+        let Some(task) = self.selected() else {
+            return;
+        };
+        let mut after = task.clone();
+        let action = if task.state == State::Removed {
+            after.state = State::Pending;
+            Action::Modify
+        } else {
+            after.state = State::Removed;
+            after.completed_at = None;
+            Action::Remove
+        };
+        let change = Change::update(task, after);
+        self.commit(action, vec![change]);
+    }
+
+    /// 标记为进行中或等待中，已处于该状态时恢复为未开始
+    fn toggle_state(&mut self, state: State, action: Action) {
+        let Some(task) = self.selected() else {
+            return;
+        };
+        let mut after = task.clone();
+        let action = if task.state == state {
+            after.state = State::Pending;
+            Action::Modify
+        } else {
+            after.state = state;
+            after.completed_at = None;
+            action
+        };
+        let change = Change::update(task, after);
+        self.commit(action, vec![change]);
+    }
+
+    fn set_priority(&mut self, f: impl FnOnce(Priority) -> Priority) {
+        // 修改优先级不会失败
+        _ = self.update(Action::Modify, |task| {
+            task.priority = f(task.priority);
+            Ok(())
+        });
+    }
+
+    /// 修改选中的任务，修改失败时不写入
+    fn update(&mut self, action: Action, f: impl FnOnce(&mut Task) -> Result<()>) -> Result<()> {
+        let Some(task) = self.selected() else {
+            return Ok(());
+        };
+        let mut after = task.clone();
+        f(&mut after)?;
+        let change = Change::update(task, after);
+        self.commit(action, vec![change]);
+        Ok(())
+    }
+
+    /// 通过与命令行相同的写入路径保存修改
+    fn commit(&mut self, action: Action, changes: Vec<Change>) {
+        match self.list.commit(action, changes) {
+            Ok(commit) if !commit.conflicts.is_empty() => {
+                let ids: Vec<String> = commit
+                    .conflicts
+                    .iter()
+                    .map(|(id, _)| id.to_string())
+                    .collect();
+                self.error(t!("tasks.modified_elsewhere", ids.join(", ")));
+            }
+            Ok(_) => (),
+            Err(e) => self.error(e.to_string()),
+        }
+        self.refresh();
+    }
+
+    fn replay(&mut self, undo: bool) {
+        let result = if undo {
+            self.list.undo(1)
+        } else {
+            self.list.redo(1)
+        };
+        match result {
+            Ok(entries) => {
+                for entry in entries {
+                    let key = if undo { "tasks.undone" } else { "tasks.redone" };
+                    self.info(t!(key, entry.seq, entry.action));
+                }
+            }
+            Err(e) => self.error(e.to_string()),
+        }
+        self.refresh();
+    }
+
+    /* 绘制 */
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let [tabs_area, list_area, status_area, help_area] = Layout::vertical([
+            Constraint::Length(2),
+            Constraint::Min(1),
+            Constraint::Length(1),
+            Constraint::Length(1),
+        ])
+        .areas(frame.area());
+
+        let titles = tabs().map(|(title, mode)| {
+            let count = self
+                .list
+                .tasks()
+                .iter()
+                .filter(|task| tasks::match_mode(task, &mode) && self.query.matches(task))
+                .count();
+            format!("{} ({})", title, count)
+        });
+        frame.render_widget(
+            Tabs::new(titles)
+                .select(self.tab)
+                .highlight_style(Style::new().yellow().bold())
+                .block(Block::new().borders(Borders::BOTTOM)),
+            tabs_area,
+        );
+
+        let items: Vec<ListItem> = self
+            .ids
+            .iter()
+            .filter_map(|id| self.list.get(*id))
+            .map(|task| ListItem::new(task_line(task)))
+            .collect();
+        frame.render_stateful_widget(
+            List::new(items).highlight_style(Style::new().reversed()),
+            list_area,
+            &mut self.state,
+        );
+
+        match &self.input {
+            None if self.confirm.is_some() => {
+                let (id, children) = self.confirm.as_ref().unwrap();
+                let message = t!("tasks.complete_children", id, children.len());
+                frame.render_widget(
+                    Paragraph::new(Line::styled(message, Style::new().cyan())),
+                    status_area,
+                );
+            }
+            Some(input) => {
+                let label = format!("{}: ", input.editing.label());
+                let before: String = input.text.chars().take(input.cursor).collect();
+                let x = status_area.x + (label.width() + before.width()) as u16;
+                frame.render_widget(
+                    Paragraph::new(Line::from(vec![
+                        Span::styled(label, Style::new().cyan()),
+                        Span::raw(input.text.as_str()),
+                    ])),
+                    status_area,
+                );
+                frame.set_cursor_position(Position::new(x, status_area.y));
+            }
+            None => {
+                let line = match &self.message {
+                    Some((message, true)) => Line::styled(message.as_str(), Style::new().red()),
+                    Some((message, false)) => Line::styled(message.as_str(), Style::new().green()),
+                    None if !self.filter.is_empty() => {
+                        Line::styled(t!("tui.filter_line", self.filter), Style::new().dark_gray())
+                    }
+                    None => Line::default(),
+                };
+                frame.render_widget(Paragraph::new(line), status_area);
+            }
+        }
+        frame.render_widget(Paragraph::new(t!("tui.help")).dark_gray(), help_area);
+    }
+}
+
+/// 与命令行中的颜色和日期格式一致的任务行
+fn task_line(task: &Task) -> Line<'static> {
+    let theme = &config::get().theme;
+    let due_to = task
+        .due_to
+        .map(|date| format!(" (due:{})", output::fmt_date(date)));
+    let recurrence = task.recurrence.map(|rec| format!(" (rec:{})", rec));
+    if task.state == State::Removed {
+        let text = format!(
+            "{:3} {}[{}] {} ({}){}{}",
+            task.id,
+            task.state,
+            task.priority,
+            task.content,
+            task.created_at.map_or("-".to_string(), output::fmt_date),
+            due_to.unwrap_or_default(),
+            recurrence.unwrap_or_default()
+        );
+        return Line::styled(text, Style::new().dark_gray());
+    }
+
+    let mut spans = vec![
+        Span::raw(format!("{:3} ", task.id)),
+        Span::styled(
+            task.state.as_str().to_string(),
+            fg(theme.state_color(&task.state)),
+        ),
+        Span::raw("["),
+        Span::styled(task.priority.as_str().to_string(), fg(theme.priority)),
+        Span::raw(format!("] {} (", task.content)),
+        Span::styled(
+            task.created_at.map_or("-".to_string(), output::fmt_date),
+            fg(theme.created),
+        ),
+        Span::raw(")"),
+    ];
+    if let Some(due_to) = due_to {
+        spans.push(Span::styled(due_to, fg(due_color(task, theme))));
+    }
+    if let Some(recurrence) = recurrence {
+        spans.push(Span::styled(recurrence, fg(theme.recurrence)));
+    }
+    if let Some(completed_at) = task.completed_at {
+        spans.push(Span::styled(
+            format!(" ({})", output::fmt_date(completed_at)),
+            fg(theme.done),
+        ));
+    }
+    Line::from(spans)
+}
+
+fn due_color(task: &Task, theme: &Theme) -> colored::Color {
+    let due_to = task.due_to.unwrap();
+    match task.state {
+        State::Completed if task.completed_at.is_some_and(|date| date > due_to) => theme.late,
+        State::Completed => theme.done,
+        _ if due_to.is_over() => theme.overdue,
+        _ => theme.due,
+    }
+}
+
+/// 将主题中的终端颜色转换为前景色样式
+fn fg(color: colored::Color) -> Style {
+    use colored::Color as C;
+    let color = match color {
+        C::Black => Color::Black,
+        C::Red => Color::Red,
+        C::Green => Color::Green,
+        C::Yellow => Color::Yellow,
+        C::Blue => Color::Blue,
+        C::Magenta => Color::Magenta,
+        C::Cyan => Color::Cyan,
+        C::White => Color::Gray,
+        C::BrightBlack => Color::DarkGray,
+        C::BrightRed => Color::LightRed,
+        C::BrightGreen => Color::LightGreen,
+        C::BrightYellow => Color::LightYellow,
+        C::BrightBlue => Color::LightBlue,
+        C::BrightMagenta => Color::LightMagenta,
+        C::BrightCyan => Color::LightCyan,
+        C::BrightWhite => Color::White,
+        C::TrueColor { r, g, b } => Color::Rgb(r, g, b),
+    };
+    Style::new().fg(color)
+}